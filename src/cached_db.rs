@@ -0,0 +1,228 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::db::{Batch, DBIterator, DB};
+use crate::error::{DBError, Result};
+
+/// Read-through decorator keeping the most recently read key/value pairs
+/// (including misses) in a bounded LRU. Writes go straight to the wrapped
+/// DB and invalidate the affected keys.
+pub struct CachedDB<D: DB> {
+    inner: D,
+    cache: RefCell<LruCache>,
+}
+
+impl<D: DB> CachedDB<D> {
+    pub fn new(inner: D, capacity: usize) -> Self {
+        CachedDB {
+            inner,
+            cache: RefCell::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    pub fn cached_len(&self) -> usize {
+        self.cache.borrow().entries.len()
+    }
+}
+
+impl<D: DB> DB for CachedDB<D> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.cache.borrow_mut().get(key) {
+            return Ok(value);
+        }
+        let value = self.inner.get(key)?;
+        self.cache.borrow_mut().put(key, value.clone());
+        Ok(value)
+    }
+
+    fn has(&self, key: &[u8]) -> Result<bool> {
+        match self.cache.borrow_mut().get(key) {
+            Some(value) => Ok(value.is_some()),
+            None => self.inner.has(key),
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.cache.get_mut().remove(key);
+        self.inner.set(key, value)
+    }
+
+    fn set_sync(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.cache.get_mut().remove(key);
+        self.inner.set_sync(key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.cache.get_mut().remove(key);
+        self.inner.delete(key)
+    }
+
+    fn delete_sync(&mut self, key: &[u8]) -> Result<()> {
+        self.cache.get_mut().remove(key);
+        self.inner.delete_sync(key)
+    }
+
+    fn new_batch(&mut self) -> Box<dyn Batch> {
+        Box::new(CachedBatch {
+            inner: RefCell::new(Some(self.inner.new_batch())),
+            keys: vec![],
+        })
+    }
+
+    fn write_batch(&mut self, batch: Box<dyn Batch>) -> Result<()> {
+        let inner = self.invalidate_batch(batch)?;
+        self.inner.write_batch(inner)
+    }
+
+    fn write_batch_sync(&mut self, batch: Box<dyn Batch>) -> Result<()> {
+        let inner = self.invalidate_batch(batch)?;
+        self.inner.write_batch_sync(inner)
+    }
+
+    /// Iterates the wrapped DB directly; writes never linger in the cache.
+    fn raw_iterator(&self) -> Box<dyn DBIterator + '_> {
+        self.inner.raw_iterator()
+    }
+
+    fn flush_wal(&self, sync: bool) -> Result<()> {
+        self.inner.flush_wal(sync)
+    }
+}
+
+impl<D: DB> CachedDB<D> {
+    fn invalidate_batch(&mut self, batch: Box<dyn Batch>) -> Result<Box<dyn Batch>> {
+        let b = batch
+            .as_any()
+            .downcast_ref::<CachedBatch>()
+            .ok_or(DBError::DownCast)?;
+        let cache = self.cache.get_mut();
+        for key in &b.keys {
+            cache.remove(key);
+        }
+        Ok(b.inner.take().ok_or(DBError::DownCast)?)
+    }
+}
+
+struct CachedBatch {
+    inner: RefCell<Option<Box<dyn Batch>>>,
+    keys: Vec<Vec<u8>>,
+}
+
+impl CachedBatch {
+    fn inner_mut(&mut self) -> &mut Box<dyn Batch> {
+        self.inner
+            .get_mut()
+            .as_mut()
+            .expect("batch already written")
+    }
+}
+
+impl Batch for CachedBatch {
+    fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.inner_mut().set(key, value)?;
+        self.keys.push(key.to_vec());
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.inner_mut().delete(key)?;
+        self.keys.push(key.to_vec());
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+struct LruCache {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<Vec<u8>, (u64, Option<Vec<u8>>)>,
+    order: BTreeMap<u64, Vec<u8>>,
+}
+
+impl LruCache {
+    fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        self.tick += 1;
+        let (tick, value) = self.entries.get_mut(key)?;
+        let key = self.order.remove(tick)?;
+        *tick = self.tick;
+        let value = value.clone();
+        self.order.insert(self.tick, key);
+        Some(value)
+    }
+
+    fn put(&mut self, key: &[u8], value: Option<Vec<u8>>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(key);
+        while self.entries.len() >= self.capacity {
+            match self.order.pop_first() {
+                Some((_, oldest)) => self.entries.remove(&oldest),
+                None => break,
+            };
+        }
+        self.tick += 1;
+        self.entries.insert(key.to_vec(), (self.tick, value));
+        self.order.insert(self.tick, key.to_vec());
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some((tick, _)) = self.entries.remove(key) {
+            self.order.remove(&tick);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::new_rocks_db;
+
+    #[test]
+    fn test_cached_db() {
+        let rocks = new_rocks_db("test_cached_db", &std::env::temp_dir()).unwrap();
+        let mut db = CachedDB::new(rocks, 2);
+        db.set(b"a", b"1").unwrap();
+        db.set(b"b", b"2").unwrap();
+        assert_eq!(Some(b"1".to_vec()), db.get(b"a").unwrap());
+        assert_eq!(None, db.get(b"missing").unwrap());
+        assert_eq!(2, db.cached_len());
+        assert_eq!(Some(b"2".to_vec()), db.get(b"b").unwrap());
+        assert_eq!(2, db.cached_len());
+
+        db.set(b"b", b"3").unwrap();
+        assert_eq!(Some(b"3".to_vec()), db.get(b"b").unwrap());
+        db.delete(b"b").unwrap();
+        assert_eq!(None, db.get(b"b").unwrap());
+
+        let mut batch = db.new_batch();
+        batch.set(b"b", b"4").unwrap();
+        db.write_batch_sync(batch).unwrap();
+        assert_eq!(Some(b"4".to_vec()), db.get(b"b").unwrap());
+        assert!(db.has(b"b").unwrap());
+
+        drop(db);
+        std::fs::remove_dir_all(std::env::temp_dir().join("test_cached_db.db")).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+use std::io::{Read, Write};
+
+use crate::error::{AvlTreeError, Error, Result};
+
+pub(crate) fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+pub(crate) fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf).map_err(eof_as_malformed)?;
+    Ok(buf)
+}
+
+/// Read a length-prefixed byte string. The buffer grows with the bytes
+/// actually read, so an untrusted length can not force a large allocation.
+pub(crate) fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let len = u32::from_be_bytes(read_array(reader)?) as u64;
+    let mut buf = vec![];
+    reader.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(AvlTreeError::MalformedEncoding.into());
+    }
+    Ok(buf)
+}
+
+/// Unsigned LEB128, as used by protobuf varints.
+pub(crate) fn write_uvarint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+pub(crate) fn read_uvarint<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let [b] = read_array(reader)?;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(AvlTreeError::MalformedEncoding.into())
+}
+
+/// An encoding that ends early is malformed input, not a failure of the
+/// underlying reader.
+pub(crate) fn eof_as_malformed(err: std::io::Error) -> Error {
+    match err.kind() {
+        std::io::ErrorKind::UnexpectedEof => AvlTreeError::MalformedEncoding.into(),
+        _ => err.into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_uvarint() {
+        for v in [0u64, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = vec![];
+            write_uvarint(&mut buf, v);
+            assert_eq!(v, read_uvarint(&mut buf.as_slice()).unwrap());
+        }
+        assert!(read_uvarint(&mut [0x80u8].as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_read_bytes() {
+        let mut buf = vec![];
+        write_bytes(&mut buf, b"abc").unwrap();
+        assert_eq!(b"abc".to_vec(), read_bytes(&mut buf.as_slice()).unwrap());
+        let err = read_bytes(&mut &buf[..5]).unwrap_err();
+        assert_eq!(ErrorKind::Corruption, err.kind());
+        assert!(read_bytes(&mut [0xffu8, 0xff, 0xff, 0xff, 0].as_slice()).is_err());
+    }
+}
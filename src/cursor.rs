@@ -0,0 +1,192 @@
+use std::cmp::Ordering;
+
+use crate::node::{Node, NodeRef};
+use crate::tree::{KeyComparator, Tree};
+
+/// Bidirectional position over a tree's entries in key order. Moving keeps
+/// the path from the root, so each step costs amortized O(1) instead of a
+/// fresh descent.
+pub struct Cursor<'a> {
+    root: &'a NodeRef,
+    /// Nodes from the root down to the current entry; empty when unpositioned.
+    path: Vec<&'a Node>,
+    compare: KeyComparator,
+}
+
+impl Tree {
+    /// An unpositioned cursor; call one of the seek methods before reading.
+    pub fn cursor(&self) -> Cursor<'_> {
+        Cursor {
+            root: &self.root,
+            path: vec![],
+            compare: self.key_cmp(),
+        }
+    }
+}
+
+impl<'a> Cursor<'a> {
+    /// Position at the first entry whose key is at or after `key`.
+    pub fn seek(&mut self, key: &[u8]) {
+        let compare = self.compare;
+        self.seek_by(|node_key| compare(node_key, key) != Ordering::Less, true)
+    }
+
+    /// Position at the last entry whose key is at or before `key`.
+    pub fn seek_for_prev(&mut self, key: &[u8]) {
+        let compare = self.compare;
+        self.seek_by(
+            |node_key| compare(node_key, key) != Ordering::Greater,
+            false,
+        )
+    }
+
+    pub fn seek_to_first(&mut self) {
+        self.path.clear();
+        self.push_extreme(self.root, true);
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.path.clear();
+        self.push_extreme(self.root, false);
+    }
+
+    pub fn next(&mut self) {
+        self.step(true)
+    }
+
+    pub fn prev(&mut self) {
+        self.step(false)
+    }
+
+    pub fn valid(&self) -> bool {
+        !self.path.is_empty()
+    }
+
+    pub fn key(&self) -> Option<&'a [u8]> {
+        Some(self.path.last()?.key.as_ref())
+    }
+
+    pub fn value(&self) -> Option<&'a [u8]> {
+        Some(self.path.last()?.value.as_ref())
+    }
+
+    /// Descend towards the boundary of `wanted`, then cut the path back to
+    /// the deepest node satisfying it. When `forward`, `wanted` holds for a
+    /// suffix of the keys and the target is its smallest; otherwise a prefix
+    /// and its largest.
+    fn seek_by<F: Fn(&[u8]) -> bool>(&mut self, wanted: F, forward: bool) {
+        self.path.clear();
+        let mut target = 0;
+        let mut node_ref = self.root;
+        while let Some(node) = node_ref {
+            self.path.push(node);
+            let matches = wanted(&node.key);
+            if matches {
+                target = self.path.len();
+            }
+            node_ref = if matches == forward {
+                &node.left
+            } else {
+                &node.right
+            };
+        }
+        self.path.truncate(target);
+    }
+
+    /// Push `node_ref` and its chain of left (`leftmost`) or right children.
+    fn push_extreme(&mut self, mut node_ref: &'a NodeRef, leftmost: bool) {
+        while let Some(node) = node_ref {
+            self.path.push(node);
+            node_ref = if leftmost { &node.left } else { &node.right };
+        }
+    }
+
+    fn step(&mut self, forward: bool) {
+        let node = match self.path.last() {
+            Some(node) => *node,
+            None => return,
+        };
+        let child = if forward { &node.right } else { &node.left };
+        if child.is_some() {
+            return self.push_extreme(child, forward);
+        }
+        // Climb until we leave a subtree on the side we are moving away from.
+        while let Some(child) = self.path.pop() {
+            match self.path.last() {
+                Some(parent)
+                    if ((self.compare)(&parent.key, &child.key) == Ordering::Greater)
+                        == forward =>
+                {
+                    return
+                }
+                Some(_) => {}
+                None => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tree() -> Tree {
+        let mut tree = Tree::new();
+        for i in (0u32..100u32).map(|i| i * 2) {
+            tree.insert(&i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn test_cursor_walks_in_order() {
+        let tree = tree();
+        let mut cursor = tree.cursor();
+        assert!(!cursor.valid());
+        cursor.seek_to_first();
+        let mut keys = vec![];
+        while let Some(key) = cursor.key() {
+            keys.push(u32::from_be_bytes(key.try_into().unwrap()));
+            cursor.next();
+        }
+        assert_eq!((0u32..100u32).map(|i| i * 2).collect::<Vec<_>>(), keys);
+
+        cursor.seek_to_last();
+        let mut keys = vec![];
+        while let Some(key) = cursor.key() {
+            keys.push(u32::from_be_bytes(key.try_into().unwrap()));
+            cursor.prev();
+        }
+        assert_eq!(
+            (0u32..100u32).rev().map(|i| i * 2).collect::<Vec<_>>(),
+            keys
+        );
+    }
+
+    #[test]
+    fn test_cursor_seek() {
+        let tree = tree();
+        let mut cursor = tree.cursor();
+        cursor.seek(&51u32.to_be_bytes());
+        assert_eq!(Some(&52u32.to_be_bytes()[..]), cursor.key());
+        assert_eq!(Some(&52u32.to_le_bytes()[..]), cursor.value());
+        cursor.prev();
+        assert_eq!(Some(&50u32.to_be_bytes()[..]), cursor.key());
+
+        cursor.seek(&52u32.to_be_bytes());
+        assert_eq!(Some(&52u32.to_be_bytes()[..]), cursor.key());
+        cursor.seek_for_prev(&51u32.to_be_bytes());
+        assert_eq!(Some(&50u32.to_be_bytes()[..]), cursor.key());
+        cursor.next();
+        assert_eq!(Some(&52u32.to_be_bytes()[..]), cursor.key());
+
+        cursor.seek(&199u32.to_be_bytes());
+        assert!(!cursor.valid());
+        cursor.seek_for_prev(&0u32.to_be_bytes());
+        assert_eq!(Some(&0u32.to_be_bytes()[..]), cursor.key());
+        cursor.prev();
+        assert!(!cursor.valid());
+        cursor.next();
+        assert!(!cursor.valid());
+    }
+}
@@ -1,17 +1,20 @@
-use anyhow::*;
-use rocksdb::{BlockBasedOptions, Cache, Options, ReadOptions, WriteOptions};
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::{BlockBasedOptions, Cache, Options, ReadOptions, SstFileWriter, WriteOptions};
 use std::any::Any;
 use std::cell::RefCell;
 use std::path::Path;
 use std::rc::Rc;
 
-use crate::error::DBError;
+use crate::error::{DBError, Result};
+use crate::stats::DBStats;
 
 pub trait DB {
     fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
 
     fn has(&self, key: &[u8]) -> Result<bool>;
 
+    /// A zero-length value is stored as is; `get` then returns `Some(vec![])`,
+    /// distinct from the `None` of an absent key.
     fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()>;
 
     fn set_sync(&mut self, key: &[u8], value: &[u8]) -> Result<()>;
@@ -25,6 +28,50 @@ pub trait DB {
     fn write_batch(&mut self, batch: Box<dyn Batch>) -> Result<()>;
 
     fn write_batch_sync(&mut self, batch: Box<dyn Batch>) -> Result<()>;
+
+    /// An unpositioned cursor over the whole keyspace; call one of the seek
+    /// methods before reading from it.
+    fn raw_iterator(&self) -> Box<dyn DBIterator + '_>;
+
+    /// Write buffered WAL entries out, fsyncing them if `sync` is set.
+    /// Backends without a write-ahead log have nothing to do.
+    fn flush_wal(&self, _sync: bool) -> Result<()> {
+        Ok(())
+    }
+
+    /// Replace the value of `key` with `new` (`None` deletes it) only if its
+    /// current value is `expected` (`None` meaning absent). Returns whether
+    /// the swap happened. The default read-then-write is atomic because it
+    /// holds `&mut self` and the backend is locked to a single process;
+    /// backends shared across processes must override it.
+    fn compare_and_swap(
+        &mut self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool> {
+        if self.get(key)?.as_deref() != expected {
+            return Ok(false);
+        }
+        match new {
+            Some(value) => self.set_sync(key, value)?,
+            None => self.delete_sync(key)?,
+        }
+        Ok(true)
+    }
+
+    /// Return the value of `key`, first storing `f()` if it is absent.
+    fn get_or_insert_with<F: FnOnce() -> Vec<u8>>(&mut self, key: &[u8], f: F) -> Result<Vec<u8>>
+    where
+        Self: Sized,
+    {
+        if let Some(value) = self.get(key)? {
+            return Ok(value);
+        }
+        let value = f();
+        self.set_sync(key, &value)?;
+        Ok(value)
+    }
 }
 
 pub trait Batch {
@@ -35,6 +82,33 @@ pub trait Batch {
     fn as_any(&self) -> &dyn Any;
 }
 
+/// Bidirectional cursor over a DB's keys in byte order. `key` and `value`
+/// return `None` once the cursor has moved past either end.
+pub trait DBIterator {
+    /// Position at the first key at or after `key`.
+    fn seek(&mut self, key: &[u8]);
+
+    /// Position at the last key at or before `key`.
+    fn seek_for_prev(&mut self, key: &[u8]);
+
+    fn seek_to_first(&mut self);
+
+    fn seek_to_last(&mut self);
+
+    fn next(&mut self);
+
+    fn prev(&mut self);
+
+    fn valid(&self) -> bool;
+
+    fn key(&self) -> Option<&[u8]>;
+
+    fn value(&self) -> Option<&[u8]>;
+
+    /// Error that invalidated the cursor, if any.
+    fn status(&self) -> Result<()>;
+}
+
 #[derive(Clone)]
 pub struct RocksDB {
     inner: Rc<Inner>,
@@ -42,34 +116,131 @@ pub struct RocksDB {
 
 struct Inner {
     db: rocksdb::DB,
+    opts: rocksdb::Options,
     ro: rocksdb::ReadOptions,
     wo: rocksdb::WriteOptions,
     wo_sync: rocksdb::WriteOptions,
 }
 
+/// Settings applied when opening a `RocksDB`.
+#[derive(Clone, Default)]
+pub struct RocksDBOptions {
+    block_cache: Option<Cache>,
+    disable_wal: bool,
+    manual_wal_flush: bool,
+    max_total_wal_size: Option<u64>,
+    statistics: bool,
+}
+
+impl RocksDBOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `cache` as the block cache instead of allocating a private 1 GiB
+    /// one, so several databases can share a single memory budget.
+    pub fn with_block_cache(mut self, cache: Cache) -> Self {
+        self.block_cache = Some(cache);
+        self
+    }
+
+    /// Skip the write-ahead log, e.g. for bulk imports. Writes, including the
+    /// `_sync` variants, are only durable once memtables are flushed, which
+    /// happens at the latest when the DB is dropped; reopen without this
+    /// option once the import is done.
+    pub fn with_wal_disabled(mut self) -> Self {
+        self.disable_wal = true;
+        self
+    }
+
+    /// Buffer WAL writes in memory until `DB::flush_wal` is called.
+    pub fn with_manual_wal_flush(mut self) -> Self {
+        self.manual_wal_flush = true;
+        self
+    }
+
+    /// Force memtable flushes once the WAL files exceed `bytes` in total.
+    pub fn with_max_total_wal_size(mut self, bytes: u64) -> Self {
+        self.max_total_wal_size = Some(bytes);
+        self
+    }
+
+    /// Collect the ticker counts reported by `RocksDB::stats`. Off by default,
+    /// as RocksDB then updates them on every read and write.
+    pub fn with_statistics(mut self) -> Self {
+        self.statistics = true;
+        self
+    }
+}
+
+/// Build an LRU block cache of `capacity` bytes to share between databases
+/// through `RocksDBOptions::with_block_cache`.
+pub fn new_block_cache(capacity: usize) -> Result<Cache> {
+    Cache::new_lru_cache(capacity).map_err(|e| DBError::WrapError(e.to_string()).into())
+}
+
+const DEFAULT_BLOCK_CACHE_SIZE: usize = 1 << 30;
+
 pub fn new_rocks_db(name: &str, dir: &Path) -> Result<RocksDB> {
+    new_rocks_db_with_options(name, dir, &RocksDBOptions::default())
+}
+
+pub fn new_rocks_db_with_options(
+    name: &str,
+    dir: &Path,
+    options: &RocksDBOptions,
+) -> Result<RocksDB> {
+    open(&dir.join(format!("{}.db", name)), options, true)
+}
+
+/// Open the existing database in exactly `path`, e.g. one created by other
+/// tooling. Fails with `DBError::NotADatabase` rather than creating one.
+pub fn open_at(path: &Path, options: &RocksDBOptions) -> Result<RocksDB> {
+    if !path.join("CURRENT").is_file() {
+        return Err(DBError::NotADatabase(path.to_path_buf()).into());
+    }
+    open(path, options, false)
+}
+
+fn open(path: &Path, options: &RocksDBOptions, create_if_missing: bool) -> Result<RocksDB> {
     let mut bbto = BlockBasedOptions::default();
-    let cache = Cache::new_lru_cache(1 << 30).map_err(|e| DBError::WrapError(e.to_string()))?;
+    let cache = match &options.block_cache {
+        Some(cache) => cache.clone(),
+        None => new_block_cache(DEFAULT_BLOCK_CACHE_SIZE)?,
+    };
     bbto.set_block_cache(&cache);
     bbto.set_bloom_filter(10.0, true);
 
     let mut opts = Options::default();
     opts.set_block_based_table_factory(&bbto);
-    opts.create_if_missing(true);
+    opts.create_if_missing(create_if_missing);
     opts.increase_parallelism(num_cpus::get() as i32);
     opts.optimize_level_style_compaction(512 * 1024 * 1024);
+    if options.statistics {
+        opts.enable_statistics();
+    }
+    opts.set_manual_wal_flush(options.manual_wal_flush);
+    if let Some(size) = options.max_total_wal_size {
+        opts.set_max_total_wal_size(size);
+    }
 
-    let db_path = dir.join(format!("{}.db", name));
-    let db = rocksdb::DB::open(&opts, db_path).map_err(|e| DBError::WrapError(e.to_string()))?;
+    let db = rocksdb::DB::open(&opts, path).map_err(|e| DBError::WrapError(e.to_string()))?;
 
     let ro = ReadOptions::default();
-    let wo = WriteOptions::default();
+    let mut wo = WriteOptions::default();
     let mut wo_sync = WriteOptions::default();
-    wo_sync.set_sync(true);
+    if options.disable_wal {
+        // RocksDB rejects sync writes without a WAL.
+        wo.disable_wal(true);
+        wo_sync.disable_wal(true);
+    } else {
+        wo_sync.set_sync(true);
+    }
 
     Ok(RocksDB {
         inner: Rc::new(Inner {
             db,
+            opts,
             ro,
             wo,
             wo_sync,
@@ -77,6 +248,120 @@ pub fn new_rocks_db(name: &str, dir: &Path) -> Result<RocksDB> {
     })
 }
 
+impl RocksDB {
+    /// Cache, filter and memtable figures for tuning cache sizing. Ticker
+    /// counts are zero unless the database was opened with
+    /// `RocksDBOptions::with_statistics`.
+    pub fn stats(&self) -> Result<DBStats> {
+        let tickers = self.inner.opts.get_statistics().unwrap_or_default();
+        Ok(DBStats {
+            block_cache_hits: ticker(&tickers, "rocksdb.block.cache.hit"),
+            block_cache_misses: ticker(&tickers, "rocksdb.block.cache.miss"),
+            block_cache_usage: self.property("rocksdb.block-cache-usage")?,
+            bloom_filter_useful: ticker(&tickers, "rocksdb.bloom.filter.useful"),
+            bloom_filter_true_positive: ticker(&tickers, "rocksdb.bloom.filter.full.true.positive"),
+            memtable_bytes: self.property("rocksdb.cur-size-all-mem-tables")?,
+            memtable_entries: self.property("rocksdb.num-entries-active-mem-table")?,
+        })
+    }
+
+    /// Write a consistent copy of the live database to `path`, which must not
+    /// exist yet. Files are hard-linked where possible, so this is cheap on
+    /// the same filesystem. The copy can be opened with `open_at`.
+    pub fn checkpoint(&self, path: &Path) -> Result<()> {
+        Checkpoint::new(&self.inner.db)
+            .and_then(|checkpoint| checkpoint.create_checkpoint(path))
+            .map_err(|e| DBError::WrapError(e.to_string()).into())
+    }
+
+    /// Exact bytes of keys and values in `[start, end)`, an unbounded end
+    /// when `None`. This scans the whole range, so it costs O(data) and
+    /// measures uncompressed payload, not the size of files on disk.
+    pub fn range_payload_size(&self, start: &[u8], end: Option<&[u8]>) -> Result<u64> {
+        let mut iter = self.raw_iterator();
+        iter.seek(start);
+        let mut size = 0;
+        while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+            if matches!(end, Some(end) if key >= end) {
+                break;
+            }
+            size += (key.len() + value.len()) as u64;
+            iter.next();
+        }
+        iter.status()?;
+        Ok(size)
+    }
+
+    /// Load `entries`, whose keys must be strictly ascending, by writing them
+    /// to an SST file at `sst_path` and ingesting that file directly,
+    /// bypassing the memtable and WAL. Existing keys are overwritten. The file
+    /// is removed afterwards, whether or not the load succeeded.
+    pub fn bulk_load<'a, I>(&self, entries: I, sst_path: &Path) -> Result<()>
+    where
+        I: IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    {
+        let result = self.write_sst(entries, sst_path).and_then(|written| {
+            if !written {
+                return Ok(());
+            }
+            self.inner
+                .db
+                .ingest_external_file(vec![sst_path])
+                .map_err(|e| DBError::WrapError(e.to_string()).into())
+        });
+        let _ = std::fs::remove_file(sst_path);
+        result
+    }
+
+    /// Returns whether any entry was written; RocksDB refuses empty SST files.
+    fn write_sst<'a, I>(&self, entries: I, sst_path: &Path) -> Result<bool>
+    where
+        I: IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    {
+        let mut writer = SstFileWriter::create(&self.inner.opts);
+        writer
+            .open(sst_path)
+            .map_err(|e| DBError::WrapError(e.to_string()))?;
+        let mut last: Option<&[u8]> = None;
+        for (key, value) in entries {
+            if key.is_empty() {
+                return Err(DBError::EmptyKey.into());
+            }
+            if matches!(last, Some(last) if last >= key) {
+                return Err(DBError::UnsortedKeys.into());
+            }
+            writer
+                .put(key, value)
+                .map_err(|e| DBError::WrapError(e.to_string()))?;
+            last = Some(key);
+        }
+        if last.is_none() {
+            return Ok(false);
+        }
+        writer
+            .finish()
+            .map_err(|e| DBError::WrapError(e.to_string()))?;
+        Ok(true)
+    }
+
+    fn property(&self, name: &str) -> Result<u64> {
+        self.inner
+            .db
+            .property_int_value(name)
+            .map(Option::unwrap_or_default)
+            .map_err(|e| DBError::WrapError(e.to_string()).into())
+    }
+}
+
+/// Read a ticker from RocksDB's statistics dump, whose lines look like
+/// `rocksdb.block.cache.hit COUNT : 42`.
+fn ticker(dump: &str, name: &str) -> u64 {
+    dump.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(" COUNT : "))
+        .and_then(|count| count.trim().parse().ok())
+        .unwrap_or(0)
+}
+
 impl DB for RocksDB {
     fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         if key.is_empty() {
@@ -99,9 +384,6 @@ impl DB for RocksDB {
         if key.is_empty() {
             return Err(DBError::EmptyKey.into());
         }
-        if value.is_empty() {
-            return Err(DBError::EmptyValue.into());
-        }
         self.inner
             .db
             .put_opt(key, value, &self.inner.wo)
@@ -112,9 +394,6 @@ impl DB for RocksDB {
         if key.is_empty() {
             return Err(DBError::EmptyKey.into());
         }
-        if value.is_empty() {
-            return Err(DBError::EmptyValue.into());
-        }
         self.inner
             .db
             .put_opt(key, value, &self.inner.wo_sync)
@@ -170,6 +449,19 @@ impl DB for RocksDB {
             .write_opt(b.inner.take(), &self.inner.wo_sync)
             .map_err(|e| DBError::WrapError(e.to_string()).into())
     }
+
+    fn raw_iterator(&self) -> Box<dyn DBIterator + '_> {
+        Box::new(RocksDBIterator {
+            inner: self.inner.db.raw_iterator(),
+        })
+    }
+
+    fn flush_wal(&self, sync: bool) -> Result<()> {
+        self.inner
+            .db
+            .flush_wal(sync)
+            .map_err(|e| DBError::WrapError(e.to_string()).into())
+    }
 }
 
 impl Drop for RocksDB {
@@ -188,9 +480,6 @@ impl Batch for RocksDBBatch {
         if key.is_empty() {
             return Err(DBError::EmptyKey.into());
         }
-        if value.is_empty() {
-            return Err(DBError::EmptyValue.into());
-        }
         self.inner.as_ref().borrow_mut().put(key, value);
         Ok(())
     }
@@ -208,9 +497,58 @@ impl Batch for RocksDBBatch {
     }
 }
 
+pub struct RocksDBIterator<'a> {
+    inner: rocksdb::DBRawIterator<'a>,
+}
+
+impl DBIterator for RocksDBIterator<'_> {
+    fn seek(&mut self, key: &[u8]) {
+        self.inner.seek(key)
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        self.inner.seek_for_prev(key)
+    }
+
+    fn seek_to_first(&mut self) {
+        self.inner.seek_to_first()
+    }
+
+    fn seek_to_last(&mut self) {
+        self.inner.seek_to_last()
+    }
+
+    fn next(&mut self) {
+        self.inner.next()
+    }
+
+    fn prev(&mut self) {
+        self.inner.prev()
+    }
+
+    fn valid(&self) -> bool {
+        self.inner.valid()
+    }
+
+    fn key(&self) -> Option<&[u8]> {
+        self.inner.key()
+    }
+
+    fn value(&self) -> Option<&[u8]> {
+        self.inner.value()
+    }
+
+    fn status(&self) -> Result<()> {
+        self.inner
+            .status()
+            .map_err(|e| DBError::WrapError(e.to_string()).into())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::error::ErrorKind;
 
     #[test]
     pub fn test_crud() {
@@ -225,6 +563,205 @@ mod test {
         std::fs::remove_dir_all(std::env::temp_dir().join("test_crud.db")).unwrap();
     }
 
+    #[test]
+    pub fn test_empty_value() {
+        let mut db = new_rocks_db("test_empty_value", &std::env::temp_dir()).unwrap();
+        db.set(b"key", b"").unwrap();
+        assert_eq!(Some(vec![]), db.get(b"key").unwrap());
+        assert_eq!(None, db.get(b"absent").unwrap());
+        drop(db);
+        std::fs::remove_dir_all(std::env::temp_dir().join("test_empty_value.db")).unwrap();
+    }
+
+    #[test]
+    pub fn test_compare_and_swap() {
+        let mut db = new_rocks_db("test_compare_and_swap", &std::env::temp_dir()).unwrap();
+        assert!(!db
+            .compare_and_swap(b"latest", Some(b"1"), Some(b"2"))
+            .unwrap());
+        assert!(db.compare_and_swap(b"latest", None, Some(b"1")).unwrap());
+        assert!(!db.compare_and_swap(b"latest", None, Some(b"2")).unwrap());
+        assert!(db
+            .compare_and_swap(b"latest", Some(b"1"), Some(b"2"))
+            .unwrap());
+        assert_eq!(Some(b"2".to_vec()), db.get(b"latest").unwrap());
+        assert!(db.compare_and_swap(b"latest", Some(b"2"), None).unwrap());
+        assert_eq!(None, db.get(b"latest").unwrap());
+
+        assert_eq!(
+            b"a".to_vec(),
+            db.get_or_insert_with(b"k", || b"a".to_vec()).unwrap()
+        );
+        assert_eq!(
+            b"a".to_vec(),
+            db.get_or_insert_with(b"k", || b"b".to_vec()).unwrap()
+        );
+        drop(db);
+        std::fs::remove_dir_all(std::env::temp_dir().join("test_compare_and_swap.db")).unwrap();
+    }
+
+    #[test]
+    pub fn test_iterator() {
+        let mut db = new_rocks_db("test_iterator", &std::env::temp_dir()).unwrap();
+        for key in [b"b", b"d", b"f"] {
+            db.set(key, key).unwrap();
+        }
+        let mut iter = db.raw_iterator();
+        iter.seek(b"c");
+        assert_eq!(Some(&b"d"[..]), iter.key());
+        iter.next();
+        assert_eq!(Some(&b"f"[..]), iter.value());
+        iter.next();
+        assert!(!iter.valid());
+
+        iter.seek_for_prev(b"c");
+        assert_eq!(Some(&b"b"[..]), iter.key());
+        iter.prev();
+        assert_eq!(None, iter.key());
+        iter.seek_to_last();
+        assert_eq!(Some(&b"f"[..]), iter.key());
+        iter.seek_to_first();
+        assert_eq!(Some(&b"b"[..]), iter.key());
+        assert!(iter.status().is_ok());
+        drop(iter);
+        drop(db);
+        std::fs::remove_dir_all(std::env::temp_dir().join("test_iterator.db")).unwrap();
+    }
+
+    #[test]
+    pub fn test_stats() {
+        let dump = "rocksdb.block.cache.miss COUNT : 7\nrocksdb.block.cache.hit COUNT : 42\n";
+        assert_eq!(42, ticker(dump, "rocksdb.block.cache.hit"));
+        assert_eq!(7, ticker(dump, "rocksdb.block.cache.miss"));
+        assert_eq!(0, ticker(dump, "rocksdb.bloom.filter.useful"));
+
+        let dir = std::env::temp_dir();
+        let mut db = new_rocks_db("test_stats", &dir).unwrap();
+        db.set(b"key", b"value").unwrap();
+        db.get(b"key").unwrap();
+        let stats = db.stats().unwrap();
+        assert_eq!(0, stats.block_cache_hits + stats.block_cache_misses);
+        drop(db);
+
+        let options = RocksDBOptions::new().with_statistics();
+        let db = new_rocks_db_with_options("test_stats", &dir, &options).unwrap();
+        db.get(b"key").unwrap();
+        assert!(db.stats().is_ok());
+        drop(db);
+        std::fs::remove_dir_all(std::env::temp_dir().join("test_stats.db")).unwrap();
+    }
+
+    #[test]
+    pub fn test_wal_options() {
+        let dir = std::env::temp_dir();
+        let options = RocksDBOptions::new()
+            .with_manual_wal_flush()
+            .with_max_total_wal_size(64 << 20);
+        let mut db = new_rocks_db_with_options("test_wal_options", &dir, &options).unwrap();
+        db.set_sync(b"key", b"value").unwrap();
+        db.flush_wal(true).unwrap();
+        drop(db);
+
+        let options = RocksDBOptions::new().with_wal_disabled();
+        let mut db = new_rocks_db_with_options("test_wal_options", &dir, &options).unwrap();
+        db.set_sync(b"other", b"value").unwrap();
+        assert_eq!(Some(b"value".to_vec()), db.get(b"other").unwrap());
+        drop(db);
+        std::fs::remove_dir_all(dir.join("test_wal_options.db")).unwrap();
+    }
+
+    #[test]
+    pub fn test_open_at() {
+        let path = std::env::temp_dir().join("test_open_at");
+        let err = open_at(&path, &RocksDBOptions::new()).err().unwrap();
+        assert_eq!(ErrorKind::NotFound, err.kind());
+        assert!(!path.exists());
+
+        let mut db = new_rocks_db("test_open_at", &std::env::temp_dir()).unwrap();
+        db.set(b"key", b"value").unwrap();
+        drop(db);
+        let path = std::env::temp_dir().join("test_open_at.db");
+        let db = open_at(&path, &RocksDBOptions::new()).unwrap();
+        assert_eq!(Some(b"value".to_vec()), db.get(b"key").unwrap());
+        drop(db);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    pub fn test_shared_block_cache() {
+        let dir = std::env::temp_dir();
+        let options = RocksDBOptions::new().with_block_cache(new_block_cache(8 << 20).unwrap());
+        let mut first = new_rocks_db_with_options("test_shared_cache_1", &dir, &options).unwrap();
+        let mut second = new_rocks_db_with_options("test_shared_cache_2", &dir, &options).unwrap();
+        first.set(b"key", b"1").unwrap();
+        second.set(b"key", b"2").unwrap();
+        assert_eq!(Some(b"1".to_vec()), first.get(b"key").unwrap());
+        assert_eq!(Some(b"2".to_vec()), second.get(b"key").unwrap());
+        drop(first);
+        drop(second);
+        std::fs::remove_dir_all(dir.join("test_shared_cache_1.db")).unwrap();
+        std::fs::remove_dir_all(dir.join("test_shared_cache_2.db")).unwrap();
+    }
+
+    #[test]
+    pub fn test_checkpoint() {
+        let dir = std::env::temp_dir();
+        let path = dir.join("test_checkpoint.snapshot");
+        let mut db = new_rocks_db("test_checkpoint", &dir).unwrap();
+        db.set(b"key", b"before").unwrap();
+        db.checkpoint(&path).unwrap();
+        db.set(b"key", b"after").unwrap();
+        assert!(db.checkpoint(&path).is_err());
+
+        let snapshot = open_at(&path, &RocksDBOptions::new()).unwrap();
+        assert_eq!(Some(b"before".to_vec()), snapshot.get(b"key").unwrap());
+        drop(snapshot);
+        drop(db);
+        std::fs::remove_dir_all(path).unwrap();
+        std::fs::remove_dir_all(dir.join("test_checkpoint.db")).unwrap();
+    }
+
+    #[test]
+    pub fn test_bulk_load() {
+        let dir = std::env::temp_dir();
+        let sst_path = dir.join("test_bulk_load.sst");
+        let mut db = new_rocks_db("test_bulk_load", &dir).unwrap();
+        db.set(&1u32.to_be_bytes(), b"old").unwrap();
+
+        let entries: Vec<([u8; 4], [u8; 4])> = (0u32..100)
+            .map(|i| (i.to_be_bytes(), i.to_le_bytes()))
+            .collect();
+        db.bulk_load(entries.iter().map(|(k, v)| (&k[..], &v[..])), &sst_path)
+            .unwrap();
+        for (key, value) in &entries {
+            assert_eq!(Some(value.to_vec()), db.get(key).unwrap());
+        }
+        assert!(!sst_path.exists());
+
+        db.bulk_load(std::iter::empty(), &sst_path).unwrap();
+        let unsorted: [(&[u8], &[u8]); 2] = [(b"b", b""), (b"a", b"")];
+        let err = db.bulk_load(unsorted, &sst_path).unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+        assert_eq!(None, db.get(b"b").unwrap());
+        assert!(!sst_path.exists());
+
+        drop(db);
+        std::fs::remove_dir_all(dir.join("test_bulk_load.db")).unwrap();
+    }
+
+    #[test]
+    pub fn test_range_payload_size() {
+        let mut db = new_rocks_db("test_range_payload_size", &std::env::temp_dir()).unwrap();
+        db.set(b"a/1", b"xx").unwrap();
+        db.set(b"a/2", b"yyyy").unwrap();
+        db.set(b"b/1", b"z").unwrap();
+        assert_eq!(12, db.range_payload_size(b"a/", Some(b"b/")).unwrap());
+        assert_eq!(16, db.range_payload_size(b"a/", None).unwrap());
+        assert_eq!(0, db.range_payload_size(b"c/", None).unwrap());
+        drop(db);
+        std::fs::remove_dir_all(std::env::temp_dir().join("test_range_payload_size.db")).unwrap();
+    }
+
     #[test]
     pub fn test_batch() {
         let mut db = new_rocks_db("test_batch", &std::env::temp_dir()).unwrap();
@@ -0,0 +1,186 @@
+use crate::error::Result;
+use crate::tree::Tree;
+
+/// A key's slot in a tree, returned by `Tree::entry`. This is sugar over
+/// `get` and `insert`: each step searches the tree from the root again, and
+/// writes go through `Tree::insert` with all of its checks.
+pub enum Entry<'a> {
+    Occupied(OccupiedEntry<'a>),
+    Vacant(VacantEntry<'a>),
+}
+
+pub struct OccupiedEntry<'a> {
+    tree: &'a mut Tree,
+    key: Vec<u8>,
+    // Value set by `and_modify`, written by `into_value`.
+    pending: Option<Vec<u8>>,
+}
+
+pub struct VacantEntry<'a> {
+    tree: &'a mut Tree,
+    key: Vec<u8>,
+}
+
+impl Tree {
+    pub fn entry(&mut self, key: &[u8]) -> Entry<'_> {
+        let key = key.to_vec();
+        if self.get_untracked(&key).is_some() {
+            Entry::Occupied(OccupiedEntry {
+                tree: self,
+                key,
+                pending: None,
+            })
+        } else {
+            Entry::Vacant(VacantEntry { tree: self, key })
+        }
+    }
+}
+
+impl<'a> Entry<'a> {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Entry::Occupied(entry) => &entry.key,
+            Entry::Vacant(entry) => &entry.key,
+        }
+    }
+
+    /// The entry's value, first inserting `value` if it is vacant.
+    pub fn or_insert(self, value: Vec<u8>) -> Result<&'a [u8]> {
+        self.or_insert_with(|| value)
+    }
+
+    /// The entry's value, first inserting `f()` if it is vacant.
+    pub fn or_insert_with<F: FnOnce() -> Vec<u8>>(self, f: F) -> Result<&'a [u8]> {
+        match self {
+            Entry::Occupied(entry) => entry.into_value(),
+            Entry::Vacant(entry) => entry.insert(f()),
+        }
+    }
+
+    /// Replace an occupied entry's value with `f(value)`; a vacant entry is
+    /// left as is. The new value is written, and any error from writing it
+    /// returned, by the `or_insert` or `or_insert_with` that follows.
+    pub fn and_modify<F: FnOnce(&[u8]) -> Vec<u8>>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                entry.pending = Some(f(entry.get()));
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a> OccupiedEntry<'a> {
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// The value, including one set by `and_modify` but not yet written.
+    pub fn get(&self) -> &[u8] {
+        match &self.pending {
+            Some(value) => value,
+            None => self
+                .tree
+                .get_untracked(&self.key)
+                .expect("[AVL]: Occupied entry without a value"),
+        }
+    }
+
+    /// Write any value set by `and_modify`, then borrow the stored value.
+    pub fn into_value(self) -> Result<&'a [u8]> {
+        if let Some(value) = self.pending {
+            self.tree.insert(&self.key, &value)?;
+        }
+        Ok(self
+            .tree
+            .get_untracked(&self.key)
+            .expect("[AVL]: Occupied entry without a value"))
+    }
+
+    /// Replace the value, returning the stored one. A value set by
+    /// `and_modify` is discarded.
+    pub fn insert(&mut self, value: Vec<u8>) -> Result<Vec<u8>> {
+        let old = self.tree.insert(&self.key, &value)?;
+        self.pending = None;
+        Ok(old.expect("[AVL]: Occupied entry without a value"))
+    }
+
+    /// Remove the entry, returning the stored value. A value set by
+    /// `and_modify` is discarded.
+    pub fn remove(self) -> Vec<u8> {
+        self.tree
+            .remove(&self.key)
+            .expect("[AVL]: Occupied entry without a value")
+    }
+}
+
+impl<'a> VacantEntry<'a> {
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Insert `value`, subject to the same checks as `Tree::insert`.
+    pub fn insert(self, value: Vec<u8>) -> Result<&'a [u8]> {
+        self.tree.insert(&self.key, &value)?;
+        Ok(self
+            .tree
+            .get_untracked(&self.key)
+            .expect("[AVL]: Missing value after insert"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::MAX_VALUE_SIZE;
+
+    fn count(tree: &mut Tree, key: &[u8]) -> u64 {
+        let value = tree
+            .entry(key)
+            .and_modify(|v| {
+                (u64::from_be_bytes(v.try_into().unwrap()) + 1)
+                    .to_be_bytes()
+                    .to_vec()
+            })
+            .or_insert(1u64.to_be_bytes().to_vec())
+            .unwrap();
+        u64::from_be_bytes(value.try_into().unwrap())
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut tree = Tree::new();
+        assert_eq!(1, count(&mut tree, b"a"));
+        assert_eq!(2, count(&mut tree, b"a"));
+        assert_eq!(1, count(&mut tree, b"b"));
+        assert_eq!(3, count(&mut tree, b"a"));
+        assert_eq!(2, tree.len());
+
+        let value = tree.entry(b"c").or_insert_with(|| b"x".to_vec());
+        assert_eq!(b"x", value.unwrap());
+        let value = tree.entry(b"c").or_insert(b"y".to_vec());
+        assert_eq!(b"x", value.unwrap());
+        match tree.entry(b"c") {
+            Entry::Occupied(entry) => {
+                assert_eq!(b"c", entry.key());
+                assert_eq!(b"x".to_vec(), entry.remove());
+            }
+            Entry::Vacant(_) => panic!("expected an occupied entry"),
+        }
+        assert!(matches!(tree.entry(b"c"), Entry::Vacant(_)));
+        assert!(tree.entry(b"").or_insert(vec![]).is_err());
+
+        // A rejected modification surfaces from the terminal call and leaves
+        // the stored value alone.
+        tree.insert(b"k", b"v").unwrap();
+        let entry = tree.entry(b"k").and_modify(|_| vec![0; MAX_VALUE_SIZE + 1]);
+        match &entry {
+            Entry::Occupied(entry) => assert_eq!(MAX_VALUE_SIZE + 1, entry.get().len()),
+            Entry::Vacant(_) => panic!("expected an occupied entry"),
+        }
+        assert!(entry.or_insert(vec![]).is_err());
+        assert_eq!(Some(&b"v"[..]), tree.get(b"k"));
+        assert!(tree.validate());
+    }
+}
@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::proof::VerificationReport;
+
 #[derive(Error, Debug)]
 pub enum AvlTreeError {
     #[error("root hash not found")]
@@ -7,6 +9,54 @@ pub enum AvlTreeError {
 
     #[error("key and value non existence in tree")]
     ValueNonExistence,
+
+    #[error("malformed tree encoding")]
+    MalformedEncoding,
+
+    #[error("Empty key")]
+    EmptyKey,
+
+    #[error("key too large: {0} bytes")]
+    KeyTooLarge(usize),
+
+    #[error("value too large: {0} bytes")]
+    ValueTooLarge(usize),
+
+    #[error("key uses a reserved prefix")]
+    ReservedKeyPrefix,
+
+    #[error("malformed proof encoding")]
+    MalformedProof,
+
+    #[error("unsupported proof format: {0}")]
+    UnsupportedProofFormat(u8),
+
+    #[error("tree height limit {0} exceeded")]
+    HeightLimitExceeded(u32),
+
+    #[error("tree size limit {0} exceeded")]
+    SizeLimitExceeded(usize),
+
+    #[error("proof does not match root: {0}")]
+    RootMismatch(Box<VerificationReport>),
+
+    #[error("invariant violated: {0}")]
+    InvariantViolation(String),
+
+    #[error("imported entries do not match the exported root hash")]
+    ImportRootMismatch,
+
+    #[error("stored hashes of node {} do not match its contents", hex::encode(.0))]
+    NodeHashMismatch(Vec<u8>),
+
+    #[error("keys must be strictly ascending")]
+    UnsortedKeys,
+
+    #[error("page limit must be at least one")]
+    ZeroPageLimit,
+
+    #[error("proof is for a different key or value")]
+    ProofMismatch,
 }
 
 #[derive(Error, Debug)]
@@ -20,6 +70,109 @@ pub enum DBError {
     #[error("Empty key")]
     EmptyKey,
 
-    #[error("Empty value")]
-    EmptyValue,
+    #[error("no database at {0}")]
+    NotADatabase(std::path::PathBuf),
+
+    #[error("bulk load keys must be strictly ascending")]
+    UnsortedKeys,
+}
+
+#[derive(Error, Debug)]
+pub enum KeyError {
+    #[error("invalid key length: expected {expected}, got {actual}")]
+    InvalidLength { expected: usize, actual: usize },
+
+    #[error("malformed key encoding")]
+    MalformedKey,
+}
+
+/// Coarse classification of `Error`, for callers that need to react to a
+/// failure without matching every variant.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ErrorKind {
+    NotFound,
+    Corruption,
+    InvalidProof,
+    Io,
+    InvalidInput,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Tree(#[from] AvlTreeError),
+
+    #[error(transparent)]
+    DB(#[from] DBError),
+
+    #[error(transparent)]
+    Key(#[from] KeyError),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("decode error: {0}")]
+    Decode(String),
+}
+
+#[cfg(feature = "prost")]
+impl From<prost::DecodeError> for Error {
+    fn from(e: prost::DecodeError) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Tree(e) => match e {
+                AvlTreeError::RootHashNotFound => ErrorKind::NotFound,
+                AvlTreeError::ValueNonExistence
+                | AvlTreeError::MalformedProof
+                | AvlTreeError::UnsupportedProofFormat(_)
+                | AvlTreeError::RootMismatch(_) => ErrorKind::InvalidProof,
+                AvlTreeError::MalformedEncoding
+                | AvlTreeError::InvariantViolation(_)
+                | AvlTreeError::ImportRootMismatch
+                | AvlTreeError::NodeHashMismatch(_) => ErrorKind::Corruption,
+                AvlTreeError::EmptyKey
+                | AvlTreeError::KeyTooLarge(_)
+                | AvlTreeError::ValueTooLarge(_)
+                | AvlTreeError::ReservedKeyPrefix
+                | AvlTreeError::HeightLimitExceeded(_)
+                | AvlTreeError::SizeLimitExceeded(_)
+                | AvlTreeError::UnsortedKeys
+                | AvlTreeError::ZeroPageLimit
+                | AvlTreeError::ProofMismatch => ErrorKind::InvalidInput,
+            },
+            Error::DB(e) => match e {
+                DBError::WrapError(_) => ErrorKind::Io,
+                DBError::NotADatabase(_) => ErrorKind::NotFound,
+                DBError::DownCast | DBError::EmptyKey | DBError::UnsortedKeys => {
+                    ErrorKind::InvalidInput
+                }
+            },
+            Error::Key(_) => ErrorKind::InvalidInput,
+            Error::Io(_) => ErrorKind::Io,
+            Error::Decode(_) => ErrorKind::Corruption,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_kind() {
+        let err: Error = AvlTreeError::RootHashNotFound.into();
+        assert_eq!(ErrorKind::NotFound, err.kind());
+        let err: Error = AvlTreeError::EmptyKey.into();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+        let err: Error = DBError::WrapError("io".to_string()).into();
+        assert_eq!(ErrorKind::Io, err.kind());
+        assert_eq!("io", err.to_string());
+    }
 }
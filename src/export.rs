@@ -0,0 +1,134 @@
+use std::io::{Read, Write};
+
+use crate::codec::{read_array, read_bytes, write_bytes};
+use crate::error::{AvlTreeError, Result};
+use crate::hash::Hash;
+use crate::tree::Tree;
+
+/// Flat export layout: every entry in ascending key order as `FLAT_ENTRY`,
+/// key and value, followed by `FLAT_TRAILER`, the entry count and version as
+/// big-endian `u64`s, and the root hash (empty for an empty tree).
+///
+/// The root hash is that of the canonical tree which `import_flat` rebuilds
+/// from the entries, each subtree rooted at its middle entry, so any
+/// implementation can recompute it. It generally differs from the exporting
+/// tree's root hash, whose shape depends on its insertion history.
+const FLAT_ENTRY: u8 = 0;
+const FLAT_TRAILER: u8 = 1;
+
+impl Tree {
+    /// Write every entry and a trailer recording `version` to `writer`.
+    /// Returns the root hash stored in the trailer.
+    pub fn export_flat<W: Write>(&self, version: u64, writer: &mut W) -> Result<Option<Hash>> {
+        let mut entries = vec![];
+        self.for_each_with_prefix(b"", |key, value| {
+            entries.push((key.to_vec(), value.to_vec()))
+        });
+        // Always byte order, whatever comparator the tree was built with.
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        for (key, value) in &entries {
+            writer.write_all(&[FLAT_ENTRY])?;
+            write_bytes(writer, key)?;
+            write_bytes(writer, value)?;
+        }
+
+        let count = entries.len();
+        let canonical = Tree::build_balanced(&mut entries.into_iter(), count);
+        let root_hash = canonical.map(|root| root.merkle_hash);
+        writer.write_all(&[FLAT_TRAILER])?;
+        writer.write_all(&(count as u64).to_be_bytes())?;
+        writer.write_all(&version.to_be_bytes())?;
+        write_bytes(writer, root_hash.as_deref().unwrap_or_default())?;
+        Ok(root_hash)
+    }
+
+    /// Rebuild the canonical tree written by `export_flat`, checking entry
+    /// order, the entry count and the root hash. Returns the version with it.
+    pub fn import_flat<R: Read>(reader: &mut R) -> Result<(u64, Tree)> {
+        let mut tree = Tree::new();
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+        loop {
+            match read_array::<R, 1>(reader)?[0] {
+                FLAT_ENTRY => {}
+                FLAT_TRAILER => break,
+                _ => return Err(AvlTreeError::MalformedEncoding.into()),
+            }
+            let key = read_bytes(reader)?;
+            let value = read_bytes(reader)?;
+            tree.validate_entry(&key, &value)?;
+            if matches!(entries.last(), Some((last, _)) if *last >= key) {
+                return Err(AvlTreeError::MalformedEncoding.into());
+            }
+            entries.push((key, value));
+        }
+
+        let count = u64::from_be_bytes(read_array(reader)?);
+        let version = u64::from_be_bytes(read_array(reader)?);
+        let root_hash = read_bytes(reader)?;
+        if count != entries.len() as u64 {
+            return Err(AvlTreeError::MalformedEncoding.into());
+        }
+        let len = entries.len();
+        tree.root = Tree::build_balanced(&mut entries.into_iter(), len);
+        if tree.root_hash().map_or(&[][..], |hash| hash.as_slice()) != root_hash.as_slice() {
+            return Err(AvlTreeError::ImportRootMismatch.into());
+        }
+        Ok((version, tree))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    fn export(tree: &Tree, version: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+        tree.export_flat(version, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_flat_round_trip() {
+        let mut forward = Tree::new();
+        let mut backward = Tree::new();
+        for i in 0u32..500u32 {
+            forward.insert(&i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+            let j = 499 - i;
+            backward.insert(&j.to_be_bytes(), &j.to_le_bytes()).unwrap();
+        }
+        let bytes = export(&forward, 42);
+        assert_eq!(bytes, export(&backward, 42));
+
+        let (version, imported) = Tree::import_flat(&mut bytes.as_slice()).unwrap();
+        assert_eq!(42, version);
+        assert!(imported.validate());
+        for i in 0u32..500u32 {
+            assert_eq!(Some(&i.to_le_bytes()[..]), imported.get(&i.to_be_bytes()));
+        }
+        assert_eq!(bytes, export(&imported, 42));
+
+        let (_, empty) = Tree::import_flat(&mut export(&Tree::new(), 1).as_slice()).unwrap();
+        assert!(empty.root.is_none());
+    }
+
+    #[test]
+    fn test_flat_import_rejects_tampering() {
+        let mut tree = Tree::new();
+        for key in [b"a", b"b", b"c"] {
+            tree.insert(key, key).unwrap();
+        }
+        let bytes = export(&tree, 7);
+        let value_offset = 1 + 4 + 1 + 4;
+        let mut tampered = bytes.clone();
+        tampered[value_offset] = b'x';
+        let err = Tree::import_flat(&mut tampered.as_slice()).unwrap_err();
+        assert_eq!(ErrorKind::Corruption, err.kind());
+
+        let mut reordered = bytes.clone();
+        reordered.swap(5, 16);
+        assert!(Tree::import_flat(&mut reordered.as_slice()).is_err());
+        let err = Tree::import_flat(&mut &bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(ErrorKind::Corruption, err.kind());
+    }
+}
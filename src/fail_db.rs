@@ -0,0 +1,170 @@
+use std::cell::Cell;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use crate::db::{Batch, DBIterator, DB};
+use crate::error::Result;
+
+/// What happens to a write selected by `FailDB::fail_every`.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Fault {
+    /// Return an IO error without writing.
+    Error,
+    /// Sleep, then perform the write.
+    Delay(Duration),
+    /// Report success without writing.
+    Drop,
+}
+
+/// Test decorator that injects faults into every Nth write (single writes and
+/// whole batches alike) of the wrapped DB. Reads are passed through.
+pub struct FailDB<D: DB> {
+    inner: D,
+    every: u64,
+    fault: Fault,
+    writes: Cell<u64>,
+}
+
+impl<D: DB> FailDB<D> {
+    /// Wrap `inner` without injecting any faults.
+    pub fn new(inner: D) -> Self {
+        FailDB {
+            inner,
+            every: 0,
+            fault: Fault::Error,
+            writes: Cell::new(0),
+        }
+    }
+
+    /// Apply `fault` to every `n`th write from now on; `0` disables injection.
+    pub fn fail_every(mut self, n: u64, fault: Fault) -> Self {
+        self.every = n;
+        self.fault = fault;
+        self.writes.set(0);
+        self
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Count a write and decide whether it should reach the wrapped DB.
+    fn before_write(&self) -> Result<bool> {
+        let writes = self.writes.get() + 1;
+        if self.every == 0 || writes < self.every {
+            self.writes.set(writes);
+            return Ok(true);
+        }
+        self.writes.set(0);
+        match self.fault {
+            Fault::Error => Err(io::Error::other("injected write fault").into()),
+            Fault::Delay(duration) => {
+                thread::sleep(duration);
+                Ok(true)
+            }
+            Fault::Drop => Ok(false),
+        }
+    }
+}
+
+impl<D: DB> DB for FailDB<D> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn has(&self, key: &[u8]) -> Result<bool> {
+        self.inner.has(key)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if self.before_write()? {
+            self.inner.set(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn set_sync(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if self.before_write()? {
+            self.inner.set_sync(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        if self.before_write()? {
+            self.inner.delete(key)?;
+        }
+        Ok(())
+    }
+
+    fn delete_sync(&mut self, key: &[u8]) -> Result<()> {
+        if self.before_write()? {
+            self.inner.delete_sync(key)?;
+        }
+        Ok(())
+    }
+
+    fn new_batch(&mut self) -> Box<dyn Batch> {
+        self.inner.new_batch()
+    }
+
+    fn write_batch(&mut self, batch: Box<dyn Batch>) -> Result<()> {
+        if self.before_write()? {
+            self.inner.write_batch(batch)?;
+        }
+        Ok(())
+    }
+
+    fn write_batch_sync(&mut self, batch: Box<dyn Batch>) -> Result<()> {
+        if self.before_write()? {
+            self.inner.write_batch_sync(batch)?;
+        }
+        Ok(())
+    }
+
+    fn raw_iterator(&self) -> Box<dyn DBIterator + '_> {
+        self.inner.raw_iterator()
+    }
+
+    fn flush_wal(&self, sync: bool) -> Result<()> {
+        self.inner.flush_wal(sync)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::new_rocks_db;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_fail_db() {
+        let rocks = new_rocks_db("test_fail_db", &std::env::temp_dir()).unwrap();
+        let mut db = FailDB::new(rocks).fail_every(3, Fault::Error);
+        db.set(b"a", b"1").unwrap();
+        db.set(b"b", b"2").unwrap();
+        let err = db.set(b"c", b"3").unwrap_err();
+        assert_eq!(ErrorKind::Io, err.kind());
+        assert_eq!(None, db.get(b"c").unwrap());
+
+        let mut db = db.fail_every(2, Fault::Drop);
+        db.set(b"c", b"3").unwrap();
+        let mut batch = db.new_batch();
+        batch.set(b"d", b"4").unwrap();
+        db.write_batch(batch).unwrap();
+        assert_eq!(Some(b"3".to_vec()), db.get(b"c").unwrap());
+        assert_eq!(None, db.get(b"d").unwrap());
+
+        let mut db = db.fail_every(1, Fault::Delay(Duration::from_millis(1)));
+        db.delete(b"a").unwrap();
+        assert_eq!(None, db.get(b"a").unwrap());
+
+        drop(db);
+        std::fs::remove_dir_all(std::env::temp_dir().join("test_fail_db.db")).unwrap();
+    }
+}
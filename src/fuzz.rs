@@ -0,0 +1,63 @@
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::proof::{Proof, ProofPathNode};
+use crate::tree::{Op, Tree};
+
+impl<'a> Arbitrary<'a> for Op {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.arbitrary()? {
+            true => Op::Set(u.arbitrary()?, u.arbitrary()?),
+            false => Op::Delete(u.arbitrary()?),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for ProofPathNode {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(ProofPathNode {
+            prefix: u.arbitrary()?,
+            suffix: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Proof {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Proof {
+            key: u.arbitrary()?,
+            value: u.arbitrary()?,
+            path: u.arbitrary()?,
+        })
+    }
+}
+
+/// A well-formed tree built by applying arbitrary writes, so its
+/// `serialize` output gives fuzzers valid node encodings to mutate. Writes
+/// the tree rejects, such as empty keys, are skipped.
+impl<'a> Arbitrary<'a> for Tree {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut tree = Tree::new();
+        for op in u.arbitrary::<Vec<Op>>()? {
+            let _ = tree.apply(&op);
+        }
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_arbitrary_inputs() {
+        let data: Vec<u8> = (0u8..=255).cycle().take(4096).collect();
+        let mut u = Unstructured::new(&data);
+        let tree: Tree = u.arbitrary().unwrap();
+        let mut bytes = vec![];
+        tree.serialize(&mut bytes).unwrap();
+        assert_eq!(tree, Tree::deserialize(&mut bytes.as_slice()).unwrap());
+
+        let proof: Proof = u.arbitrary().unwrap();
+        assert_eq!(proof, Proof::decode(&proof.encode()).unwrap());
+    }
+}
@@ -1,5 +1,7 @@
 use sha2::{Digest, Sha256};
 
+use crate::codec::write_uvarint;
+
 pub type Hash = Vec<u8>;
 
 pub fn hash_value(bytes: &[u8]) -> Hash {
@@ -18,6 +20,23 @@ pub fn hash_array(bytes_array: &[&[u8]]) -> Hash {
     hash.to_vec()
 }
 
+/// Hash of a node's own entry. Key and value are each length-prefixed, so
+/// moving bytes between them changes the hash.
+pub fn leaf_hash(key: &[u8], value: &[u8]) -> Hash {
+    let mut key_len = vec![];
+    write_uvarint(&mut key_len, key.len() as u64);
+    let mut value_len = vec![];
+    write_uvarint(&mut value_len, value.len() as u64);
+    hash_array(&[&key_len, key, &value_len, value])
+}
+
+/// Hash many independent preimages, each given as a list of parts, in one
+/// call. Results are in input order; this is the entry point for batched or
+/// multi-buffer SHA-256 backends.
+pub fn hash_many(preimages: &[&[&[u8]]]) -> Vec<Hash> {
+    preimages.iter().map(|parts| hash_array(parts)).collect()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -38,4 +57,19 @@ mod test {
         let result = hash_array(&[b"h", b"e", b"l", b"l", b"o"]);
         assert_eq!(Sha256::digest(b"hello").to_vec(), result);
     }
+
+    #[test]
+    fn test_leaf_hash() {
+        assert_eq!(hash_value(b"\x01a\x01b"), leaf_hash(b"a", b"b"));
+        assert_ne!(leaf_hash(b"a", b"b"), leaf_hash(b"ab", b""));
+    }
+
+    #[test]
+    fn test_hash_many() {
+        let result = hash_many(&[&[b"hel", b"lo"], &[], &[b"world"]]);
+        assert_eq!(
+            vec![hash_value(b"hello"), hash_value(b""), hash_value(b"world")],
+            result
+        );
+    }
 }
@@ -0,0 +1,170 @@
+use crate::error::{AvlTreeError, Result};
+use crate::keys::{decode_composite, encode_composite};
+use crate::tree::{Op, Tree};
+
+/// Namespace holding index entries; primary keys may not start with it.
+pub const INDEX_NAMESPACE: &[u8] = b"\xffindex/";
+
+/// Derives the secondary key of an entry from its primary key and value,
+/// or `None` if the entry should not be indexed.
+pub type IndexFn = Box<dyn Fn(&[u8], &[u8]) -> Option<Vec<u8>>>;
+
+struct Index {
+    name: Vec<u8>,
+    extract: IndexFn,
+}
+
+/// A `Tree` that keeps registered secondary indexes in sync with every
+/// primary write. Index entries live in the same tree under
+/// `INDEX_NAMESPACE`, so they are covered by the root hash.
+pub struct IndexedTree {
+    tree: Tree,
+    indexes: Vec<Index>,
+}
+
+impl IndexedTree {
+    pub fn new(tree: Tree) -> Self {
+        IndexedTree {
+            tree,
+            indexes: vec![],
+        }
+    }
+
+    /// Register an index. Only entries written after registration are indexed.
+    pub fn register_index(&mut self, name: &[u8], extract: IndexFn) {
+        self.indexes.push(Index {
+            name: name.to_vec(),
+            extract,
+        });
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.tree.get(key)
+    }
+
+    /// Insert the primary entry and update every index in one atomic batch.
+    /// Nothing is written if the primary entry or any of its index entries is
+    /// rejected, or if together they would exceed the tree's limits.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        if key.starts_with(INDEX_NAMESPACE) {
+            return Err(AvlTreeError::ReservedKeyPrefix.into());
+        }
+        let mut ops = vec![Op::Set(key.to_vec(), value.to_vec())];
+        if let Some(old_value) = self.tree.get_untracked(key) {
+            ops.extend(self.index_keys(key, old_value).into_iter().map(Op::Delete));
+        }
+        // Sets come after the deletes, so an index entry the new value keeps
+        // survives the batch.
+        let entries = self.index_keys(key, value);
+        ops.extend(entries.into_iter().map(|entry| Op::Set(entry, vec![])));
+        let mut previous = self.tree.apply_batch(&ops)?;
+        Ok(previous.swap_remove(0))
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        if key.starts_with(INDEX_NAMESPACE) {
+            return None;
+        }
+        let old_value = self.tree.remove(key)?;
+        for entry in self.index_keys(key, &old_value) {
+            self.tree.remove(&entry);
+        }
+        Some(old_value)
+    }
+
+    /// Primary keys whose entries map to `secondary` in the index `name`, in
+    /// ascending order.
+    pub fn query(&self, name: &[u8], secondary: &[u8]) -> Vec<Vec<u8>> {
+        let prefix = encode_composite(&[INDEX_NAMESPACE, name, secondary]);
+        let mut keys = vec![];
+        self.tree.for_each_with_prefix(&prefix, |entry, _| {
+            if let Some(primary) = decode_composite(entry).ok().and_then(|mut c| c.pop()) {
+                keys.push(primary);
+            }
+        });
+        keys
+    }
+
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> Tree {
+        self.tree
+    }
+
+    fn index_keys(&self, key: &[u8], value: &[u8]) -> Vec<Vec<u8>> {
+        self.indexes
+            .iter()
+            .filter_map(|index| {
+                let secondary = (index.extract)(key, value)?;
+                Some(encode_composite(&[
+                    INDEX_NAMESPACE,
+                    index.name.as_ref(),
+                    secondary.as_ref(),
+                    key,
+                ]))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn owner_index() -> IndexFn {
+        Box::new(|_, value| value.split(|b| *b == b':').next().map(|o| o.to_vec()))
+    }
+
+    #[test]
+    fn test_index_maintenance() {
+        let mut tree = IndexedTree::new(Tree::new());
+        tree.register_index(b"owner", owner_index());
+        tree.insert(b"nft1", b"alice:red").unwrap();
+        tree.insert(b"nft2", b"bob:blue").unwrap();
+        tree.insert(b"nft3", b"alice:green").unwrap();
+        assert_eq!(
+            vec![b"nft1".to_vec(), b"nft3".to_vec()],
+            tree.query(b"owner", b"alice")
+        );
+
+        tree.insert(b"nft1", b"bob:red").unwrap();
+        assert_eq!(vec![b"nft3".to_vec()], tree.query(b"owner", b"alice"));
+        assert_eq!(
+            vec![b"nft1".to_vec(), b"nft2".to_vec()],
+            tree.query(b"owner", b"bob")
+        );
+
+        assert_eq!(Some(b"bob:blue".to_vec()), tree.remove(b"nft2"));
+        assert_eq!(vec![b"nft1".to_vec()], tree.query(b"owner", b"bob"));
+        assert!(tree.query(b"owner", b"carol").is_empty());
+    }
+
+    #[test]
+    fn test_rejected_write_leaves_tree_untouched() {
+        let mut tree = IndexedTree::new(Tree::new());
+        tree.register_index(b"owner", owner_index());
+        assert!(tree.insert(INDEX_NAMESPACE, b"alice:red").is_err());
+        assert!(tree.insert(b"", b"alice:red").is_err());
+        assert!(tree.tree().root.is_none());
+
+        let mut tree = IndexedTree::new(Tree::new().with_max_size(1));
+        tree.register_index(b"owner", owner_index());
+        assert!(tree.insert(b"nft1", b"alice:red").is_err());
+        assert!(tree.tree().root.is_none());
+    }
+
+    #[test]
+    fn test_overwrite_keeping_secondary() {
+        let mut tree = IndexedTree::new(Tree::new());
+        tree.register_index(b"owner", owner_index());
+        tree.insert(b"nft1", b"alice:red").unwrap();
+        assert_eq!(
+            Some(b"alice:red".to_vec()),
+            tree.insert(b"nft1", b"alice:blue").unwrap()
+        );
+        assert_eq!(vec![b"nft1".to_vec()], tree.query(b"owner", b"alice"));
+        assert_eq!(2, tree.tree().len());
+    }
+}
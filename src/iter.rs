@@ -0,0 +1,365 @@
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+use crate::node::{Node, NodeRef};
+use crate::tree::{KeyComparator, Tree};
+
+/// Iterator over a tree's entries in key order, returned by `Tree::iter` and
+/// `Tree::iter_range`. Ascending by default; use `rev()` to scan downwards.
+/// `size_hint` is exact, except for prefix scans under a custom comparator,
+/// whose matches are only known as they are reached.
+pub struct Iter<'a> {
+    /// Entries between the two ends not yet visited from either.
+    remaining: usize,
+    /// Nodes whose entry and right subtree are still to be yielded from the
+    /// front, the next one on top.
+    front: Vec<&'a Node>,
+    /// Likewise for entries and left subtrees from the back.
+    back: Vec<&'a Node>,
+    compare: KeyComparator,
+    /// Prefix that yielded keys must have, for prefix scans over a tree whose
+    /// order does not keep such keys adjacent.
+    prefix: Option<Vec<u8>>,
+}
+
+/// Owning iterator over a tree's entries in ascending key order, returned by
+/// `Tree::into_iter`. Nodes are taken apart as they are yielded, and any left
+/// unvisited are freed iteratively when it is dropped.
+pub struct IntoIter {
+    remaining: usize,
+    /// Nodes whose entry and right subtree are still to be yielded, the next
+    /// one on top; their left subtrees have already been detached.
+    stack: Vec<Node>,
+}
+
+impl Tree {
+    pub fn iter(&self) -> Iter<'_> {
+        Iter::new(self, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// Entries with keys in `start..end`. Only the nodes on the boundaries'
+    /// search paths and inside the range are visited.
+    pub fn iter_range<'a>(&'a self, start: &[u8], end: &[u8]) -> Iter<'a> {
+        Iter::new(self, Bound::Included(start), Bound::Excluded(end))
+    }
+
+    /// Entries whose keys start with `prefix`, as the range from `prefix` up
+    /// to the first key past all its extensions. Under a custom comparator
+    /// every entry is visited instead, in a single pass.
+    pub fn iter_prefix<'a>(&'a self, prefix: &[u8]) -> Iter<'a> {
+        if self.comparator().is_some() {
+            let mut iter = self.iter();
+            iter.prefix = Some(prefix.to_vec());
+            return iter;
+        }
+        let end = prefix_end(prefix);
+        let end = end.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+        Iter::new(self, Bound::Included(prefix), end)
+    }
+}
+
+impl IntoIterator for Tree {
+    type Item = (Vec<u8>, Vec<u8>);
+    type IntoIter = IntoIter;
+
+    fn into_iter(mut self) -> IntoIter {
+        let mut iter = IntoIter {
+            remaining: self.len(),
+            stack: vec![],
+        };
+        iter.push_left(self.root.take());
+        iter
+    }
+}
+
+impl<'a> IntoIterator for &'a Tree {
+    type Item = (&'a [u8], &'a [u8]);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl<'a> Iter<'a> {
+    fn new(tree: &'a Tree, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Self {
+        let root = &tree.root;
+        let compare = tree.key_cmp();
+        let before_start = count_while(root, |key| !after_start(compare, key, start));
+        let before_end = count_while(root, |key| before_end(compare, key, end));
+        let mut iter = Iter {
+            remaining: before_end.saturating_sub(before_start),
+            front: vec![],
+            back: vec![],
+            compare,
+            prefix: None,
+        };
+        iter.push_front(root, start);
+        iter.push_back(root, end);
+        iter
+    }
+
+    /// Push the nodes at or after `start` along the leftmost path of
+    /// `node_ref`, skipping right past those before it.
+    fn push_front(&mut self, mut node_ref: &'a NodeRef, start: Bound<&[u8]>) {
+        while let Some(node) = node_ref {
+            if after_start(self.compare, &node.key, start) {
+                self.front.push(node);
+                node_ref = &node.left;
+            } else {
+                node_ref = &node.right;
+            }
+        }
+    }
+
+    fn wanted(&self, node: &Node) -> bool {
+        self.prefix
+            .as_ref()
+            .is_none_or(|prefix| node.key.starts_with(prefix))
+    }
+
+    /// Mirror of `push_front` along the rightmost path, up to `end`.
+    fn push_back(&mut self, mut node_ref: &'a NodeRef, end: Bound<&[u8]>) {
+        while let Some(node) = node_ref {
+            if before_end(self.compare, &node.key, end) {
+                self.back.push(node);
+                node_ref = &node.right;
+            } else {
+                node_ref = &node.left;
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining > 0 {
+            let node = self.front.pop()?;
+            self.push_front(&node.right, Bound::Unbounded);
+            self.remaining -= 1;
+            if self.wanted(node) {
+                return Some((node.key.as_ref(), node.value.as_ref()));
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.prefix {
+            Some(_) => (0, Some(self.remaining)),
+            None => (self.remaining, Some(self.remaining)),
+        }
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while self.remaining > 0 {
+            let node = self.back.pop()?;
+            self.push_back(&node.left, Bound::Unbounded);
+            self.remaining -= 1;
+            if self.wanted(node) {
+                return Some((node.key.as_ref(), node.value.as_ref()));
+            }
+        }
+        None
+    }
+}
+
+impl IntoIter {
+    fn push_left(&mut self, mut node_ref: NodeRef) {
+        while let Some(mut node) = node_ref {
+            node_ref = node.left.take();
+            self.stack.push(*node);
+        }
+    }
+}
+
+impl Iterator for IntoIter {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut node = self.stack.pop()?;
+        self.remaining -= 1;
+        self.push_left(node.right.take());
+        Some((node.key, node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for IntoIter {}
+
+impl Drop for IntoIter {
+    fn drop(&mut self) {
+        while let Some(mut node) = self.stack.pop() {
+            self.stack.extend(node.left.take().map(|left| *left));
+            self.stack.extend(node.right.take().map(|right| *right));
+        }
+    }
+}
+
+fn after_start(compare: KeyComparator, key: &[u8], start: Bound<&[u8]>) -> bool {
+    match start {
+        Bound::Included(s) => compare(key, s) != Ordering::Less,
+        Bound::Excluded(s) => compare(key, s) == Ordering::Greater,
+        Bound::Unbounded => true,
+    }
+}
+
+fn before_end(compare: KeyComparator, key: &[u8], end: Bound<&[u8]>) -> bool {
+    match end {
+        Bound::Included(e) => compare(key, e) != Ordering::Greater,
+        Bound::Excluded(e) => compare(key, e) == Ordering::Less,
+        Bound::Unbounded => true,
+    }
+}
+
+/// Smallest key greater than every key starting with `prefix`, or `None` if
+/// there is none (the prefix is empty or all `0xff`).
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+/// Number of leading keys satisfying `pred`, which must hold for a prefix of
+/// the keys in order.
+fn count_while<F: Fn(&[u8]) -> bool>(mut node_ref: &NodeRef, pred: F) -> usize {
+    let mut count = 0;
+    while let Some(node) = node_ref {
+        if pred(&node.key) {
+            count += node.left_size() + 1;
+            node_ref = &node.right;
+        } else {
+            node_ref = &node.left;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_iter() {
+        let mut tree = Tree::new();
+        assert_eq!(None, tree.iter().next());
+        for i in (0u32..200u32).rev() {
+            tree.insert(&i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+        }
+        let entries: Vec<_> = tree.iter().collect();
+        assert_eq!(200, entries.len());
+        for (i, (key, value)) in entries.into_iter().enumerate() {
+            assert_eq!(&(i as u32).to_be_bytes()[..], key);
+            assert_eq!(&(i as u32).to_le_bytes()[..], value);
+        }
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let tree =
+            Tree::from_sorted_iter((0u32..100u32).map(|i| (i.to_be_bytes().to_vec(), vec![])))
+                .unwrap();
+        let borrowed: Vec<_> = (&tree).into_iter().map(|(k, _)| k.to_vec()).collect();
+        let mut owned = tree.clone().into_iter();
+        assert_eq!(100, owned.len());
+        assert_eq!(Some((0u32.to_be_bytes().to_vec(), vec![])), owned.next());
+        assert_eq!(99, owned.len());
+        drop(owned);
+        let keys: Vec<_> = tree.into_iter().map(|(k, _)| k).collect();
+        assert_eq!(borrowed, keys);
+        assert_eq!(
+            (0u32..100u32)
+                .map(|i| i.to_be_bytes().to_vec())
+                .collect::<Vec<_>>(),
+            keys
+        );
+    }
+
+    #[test]
+    fn test_iter_range() {
+        let mut tree = Tree::new();
+        for i in (0u32..100u32).map(|i| i * 2) {
+            tree.insert(&i.to_be_bytes(), &[]).unwrap();
+        }
+        let keys = |start: u32, end: u32| -> Vec<u32> {
+            tree.iter_range(&start.to_be_bytes(), &end.to_be_bytes())
+                .map(|(key, _)| u32::from_be_bytes(key.try_into().unwrap()))
+                .collect()
+        };
+        assert_eq!(vec![10, 12, 14], keys(10, 16));
+        assert_eq!(vec![12, 14, 16], keys(11, 17));
+        assert_eq!(vec![0, 2], keys(0, 3));
+        assert_eq!(vec![196, 198], keys(195, 1000));
+        assert!(keys(20, 20).is_empty());
+        assert!(keys(30, 10).is_empty());
+        assert!(keys(199, 1000).is_empty());
+    }
+
+    #[test]
+    fn test_iter_rev() {
+        let mut tree = Tree::new();
+        for i in 0u32..100u32 {
+            tree.insert(&i.to_be_bytes(), &[]).unwrap();
+        }
+        let decode = |(key, _): (&[u8], &[u8])| u32::from_be_bytes(key.try_into().unwrap());
+        let keys: Vec<_> = tree.iter().rev().map(decode).collect();
+        assert_eq!((0u32..100u32).rev().collect::<Vec<_>>(), keys);
+
+        let mut range = tree.iter_range(&10u32.to_be_bytes(), &15u32.to_be_bytes());
+        assert_eq!((5, Some(5)), range.size_hint());
+        assert_eq!(Some(14), range.next_back().map(decode));
+        assert_eq!(Some(10), range.next().map(decode));
+        assert_eq!(Some(13), range.next_back().map(decode));
+        assert_eq!((2, Some(2)), range.size_hint());
+        assert_eq!(vec![11, 12], range.map(decode).collect::<Vec<_>>());
+
+        let range = tree.iter_range(&50u32.to_be_bytes(), &10u32.to_be_bytes());
+        assert_eq!(0, range.rev().count());
+    }
+
+    #[test]
+    fn test_iter_prefix() {
+        let mut tree = Tree::new();
+        for key in [
+            &b"a"[..],
+            b"ab",
+            b"ab\xff",
+            b"ab\xff\xff",
+            b"ac",
+            b"b",
+            b"\xff",
+            b"\xff\x01",
+        ] {
+            tree.insert(key, key).unwrap();
+        }
+        let keys = |prefix: &[u8]| -> Vec<Vec<u8>> {
+            tree.iter_prefix(prefix)
+                .map(|(key, _)| key.to_vec())
+                .collect()
+        };
+        assert_eq!(
+            vec![b"ab".to_vec(), b"ab\xff".to_vec(), b"ab\xff\xff".to_vec()],
+            keys(b"ab")
+        );
+        assert_eq!(
+            vec![b"ab\xff".to_vec(), b"ab\xff\xff".to_vec()],
+            keys(b"ab\xff")
+        );
+        assert_eq!(vec![b"\xff".to_vec(), b"\xff\x01".to_vec()], keys(b"\xff"));
+        assert_eq!(8, keys(b"").len());
+        assert!(keys(b"abc").is_empty());
+        assert_eq!(Some(b"ac".to_vec()), prefix_end(b"ab\xff"));
+    }
+}
@@ -0,0 +1,171 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{KeyError, Result};
+
+/// Encode `v` so that byte-wise ordering of encodings matches numeric ordering.
+pub fn encode_u64(v: u64) -> [u8; 8] {
+    v.to_be_bytes()
+}
+
+pub fn decode_u64(bytes: &[u8]) -> Result<u64> {
+    Ok(u64::from_be_bytes(fixed(bytes)?))
+}
+
+/// Big-endian with the sign bit flipped, so negative values sort first.
+pub fn encode_i64(v: i64) -> [u8; 8] {
+    ((v as u64) ^ SIGN_BIT).to_be_bytes()
+}
+
+pub fn decode_i64(bytes: &[u8]) -> Result<i64> {
+    Ok((u64::from_be_bytes(fixed(bytes)?) ^ SIGN_BIT) as i64)
+}
+
+/// Seconds relative to the unix epoch (sign-flipped) followed by nanoseconds,
+/// so times before the epoch still sort correctly.
+pub fn encode_time(t: SystemTime) -> [u8; 12] {
+    let (secs, nanos) = match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+            match d.subsec_nanos() {
+                0 => (-(d.as_secs() as i64), 0),
+                n => (-(d.as_secs() as i64) - 1, 1_000_000_000 - n),
+            }
+        }
+    };
+    let mut out = [0u8; 12];
+    out[..8].copy_from_slice(&encode_i64(secs));
+    out[8..].copy_from_slice(&nanos.to_be_bytes());
+    out
+}
+
+pub fn decode_time(bytes: &[u8]) -> Result<SystemTime> {
+    let bytes: [u8; 12] = fixed(bytes)?;
+    let secs = decode_i64(&bytes[..8])?;
+    let nanos = u32::from_be_bytes(fixed(&bytes[8..])?);
+    if nanos >= 1_000_000_000 {
+        return Err(KeyError::MalformedKey.into());
+    }
+    let time = if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, nanos)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + Duration::from_nanos(nanos as u64)
+    };
+    Ok(time)
+}
+
+/// Join components so that composite keys order component by component.
+/// `0x00` bytes are escaped as `0x00 0xff` and each component is terminated
+/// by `0x00 0x01`, so a shorter component always sorts before its extensions.
+pub fn encode_composite(components: &[&[u8]]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(components.iter().map(|c| c.len() + 2).sum());
+    for component in components {
+        for &b in *component {
+            out.push(b);
+            if b == 0x00 {
+                out.push(ESCAPE);
+            }
+        }
+        out.extend_from_slice(&[0x00, TERMINATOR]);
+    }
+    out
+}
+
+pub fn decode_composite(bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut components = vec![];
+    let mut current = vec![];
+    let mut iter = bytes.iter();
+    while let Some(&b) = iter.next() {
+        if b != 0x00 {
+            current.push(b);
+            continue;
+        }
+        match iter.next() {
+            Some(&ESCAPE) => current.push(0x00),
+            Some(&TERMINATOR) => components.push(std::mem::take(&mut current)),
+            _ => return Err(KeyError::MalformedKey.into()),
+        }
+    }
+    if !current.is_empty() {
+        return Err(KeyError::MalformedKey.into());
+    }
+    Ok(components)
+}
+
+const SIGN_BIT: u64 = 1 << 63;
+const ESCAPE: u8 = 0xff;
+const TERMINATOR: u8 = 0x01;
+
+fn fixed<const N: usize>(bytes: &[u8]) -> Result<[u8; N]> {
+    bytes.try_into().map_err(|_| {
+        KeyError::InvalidLength {
+            expected: N,
+            actual: bytes.len(),
+        }
+        .into()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_integer_ordering() {
+        let values = [0u64, 1, 255, 256, u32::MAX as u64, u64::MAX];
+        for w in values.windows(2) {
+            assert!(encode_u64(w[0]) < encode_u64(w[1]));
+        }
+        for v in values {
+            assert_eq!(v, decode_u64(&encode_u64(v)).unwrap());
+        }
+
+        let values = [i64::MIN, -256, -1, 0, 1, 256, i64::MAX];
+        for w in values.windows(2) {
+            assert!(encode_i64(w[0]) < encode_i64(w[1]));
+        }
+        for v in values {
+            assert_eq!(v, decode_i64(&encode_i64(v)).unwrap());
+        }
+        assert!(decode_u64(b"short").is_err());
+    }
+
+    #[test]
+    fn test_time_ordering() {
+        let times = [
+            UNIX_EPOCH - Duration::new(10, 500),
+            UNIX_EPOCH - Duration::new(10, 0),
+            UNIX_EPOCH - Duration::from_nanos(1),
+            UNIX_EPOCH,
+            UNIX_EPOCH + Duration::new(10, 1),
+        ];
+        for w in times.windows(2) {
+            assert!(encode_time(w[0]) < encode_time(w[1]));
+        }
+        for t in times {
+            assert_eq!(t, decode_time(&encode_time(t)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_composite() {
+        let keys = [
+            encode_composite(&[b"a"]),
+            encode_composite(&[b"a", b""]),
+            encode_composite(&[b"a", b"\x00"]),
+            encode_composite(&[b"a", b"b"]),
+            encode_composite(&[b"a\x00"]),
+            encode_composite(&[b"ab"]),
+        ];
+        for w in keys.windows(2) {
+            assert!(w[0] < w[1]);
+        }
+        let components: [&[u8]; 3] = [b"bank", b"\x00\xff\x01", b""];
+        assert_eq!(
+            components.to_vec(),
+            decode_composite(&encode_composite(&components)).unwrap()
+        );
+        assert!(decode_composite(b"a").is_err());
+        assert!(decode_composite(b"a\x00\x02").is_err());
+    }
+}
@@ -1,10 +1,65 @@
+pub mod cached_db;
+mod codec;
+pub mod cursor;
 pub mod db;
+pub mod entry;
 pub mod error;
+pub mod export;
+#[cfg(feature = "failpoints")]
+pub mod fail_db;
+#[cfg(feature = "arbitrary")]
+mod fuzz;
 pub mod hash;
+pub mod index;
+pub mod iter;
+pub mod keys;
 pub mod node;
+#[cfg(feature = "prost")]
+pub mod object_store;
 pub mod proof;
+mod shape;
+pub mod sharded;
+pub mod simulator;
+pub mod stats;
 pub mod tree;
+pub mod typed;
+pub mod visit;
 
+use simulator::{simulate, Workload};
+
+/// `iavl-rs simulate [--seed N] [--ops N] [--keys N] [--check-every N]` runs a
+/// seeded soak test and prints its report.
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) != Some("simulate") {
+        println!("Hello, world!");
+        return;
+    }
+
+    let mut workload = Workload::default();
+    for pair in args[1..].chunks(2) {
+        let value = pair.get(1).and_then(|v| v.parse().ok());
+        let target = match pair[0].as_str() {
+            "--seed" => &mut workload.seed,
+            "--ops" => &mut workload.ops,
+            "--keys" => &mut workload.key_space,
+            "--check-every" => &mut workload.check_every,
+            _ => usage(&pair[0]),
+        };
+        *target = value.unwrap_or_else(|| usage(&pair[0]));
+    }
+
+    match simulate(&workload) {
+        Ok(report) => println!("{:?}", report),
+        Err(e) => {
+            eprintln!("simulation failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn usage(arg: &str) -> ! {
+    eprintln!("invalid argument {}", arg);
+    eprintln!("usage: iavl-rs simulate [--seed N] [--ops N] [--keys N] [--check-every N]");
+    std::process::exit(2);
 }
@@ -1,4 +1,18 @@
-use crate::hash::{hash_array, hash_value, Hash};
+use crate::hash::{hash_array, hash_value, leaf_hash, Hash};
+use std::cell::Cell;
+
+thread_local! {
+    static NODES_HASHED: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Number of node merkle hashes computed so far on the current thread.
+pub fn nodes_hashed() -> u64 {
+    NODES_HASHED.with(|count| count.get())
+}
+
+fn record_node_hashed() {
+    NODES_HASHED.with(|count| count.set(count.get() + 1));
+}
 
 pub type NodeRef = Option<Box<Node>>;
 
@@ -9,6 +23,8 @@ pub struct Node {
     pub hash: Hash,
     pub merkle_hash: Hash,
     pub height: u32,
+    /// Number of entries in the subtree rooted at this node.
+    pub size: usize,
     pub left: NodeRef,
     pub right: NodeRef,
 }
@@ -20,14 +36,16 @@ pub fn as_node_ref(key: Vec<u8>, value: Vec<u8>) -> NodeRef {
 
 impl Node {
     fn new(key: Vec<u8>, value: Vec<u8>) -> Self {
-        let hash = hash_array(&[key.as_ref(), value.as_ref()]);
+        let hash = leaf_hash(&key, &value);
         let merkle_hash = hash_value(hash.as_ref());
+        record_node_hashed();
         Node {
             key,
             value,
             hash,
             merkle_hash,
             height: 0,
+            size: 1,
             left: None,
             right: None,
         }
@@ -62,6 +80,18 @@ impl Node {
         }
     }
 
+    fn update_size(&mut self) {
+        self.size = 1 + self.left_size() + self.right_size();
+    }
+
+    pub fn left_size(&self) -> usize {
+        self.left.as_ref().map_or(0, |left| left.size)
+    }
+
+    pub fn right_size(&self) -> usize {
+        self.right.as_ref().map_or(0, |right| right.size)
+    }
+
     fn update_hashes(&mut self) {
         let mut array: Vec<&[u8]> = Vec::new();
         if let Some(left) = &self.left {
@@ -72,10 +102,11 @@ impl Node {
             array.push(right.merkle_hash.as_ref());
         }
         self.merkle_hash = hash_array(array.as_ref());
+        record_node_hashed();
     }
 
     pub fn update_value(&mut self, value: &[u8]) -> Vec<u8> {
-        let hash = hash_array(&[self.key.as_ref(), value]);
+        let hash = leaf_hash(&self.key, value);
         self.hash = hash;
         std::mem::replace(&mut self.value, value.to_vec())
     }
@@ -83,6 +114,7 @@ impl Node {
     pub fn update(&mut self) {
         self.update_hashes();
         self.update_height();
+        self.update_size();
     }
 
     pub fn balance_factor(&self) -> i32 {
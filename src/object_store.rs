@@ -0,0 +1,126 @@
+use prost::Message;
+use std::marker::PhantomData;
+
+use crate::error::Result;
+use crate::keys::encode_composite;
+use crate::proof::Proof;
+use crate::tree::Tree;
+
+/// Typed view over a `Tree` storing protobuf-encoded messages of one type.
+/// Keys are namespaced by `type_prefix`, so several stores can share a tree.
+pub struct ObjectStore<M: Message + Default> {
+    tree: Tree,
+    type_prefix: Vec<u8>,
+    _marker: PhantomData<M>,
+}
+
+impl<M: Message + Default> ObjectStore<M> {
+    pub fn new(tree: Tree, type_prefix: &[u8]) -> Self {
+        ObjectStore {
+            tree,
+            type_prefix: type_prefix.to_vec(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Key under which `key` is stored in the underlying tree.
+    pub fn tree_key(&self, key: &[u8]) -> Vec<u8> {
+        encode_composite(&[self.type_prefix.as_ref(), key])
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<M>> {
+        match self.tree.get(&self.tree_key(key)) {
+            Some(bytes) => Ok(Some(M::decode(bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Insert `message`, returning the previously stored message, if any. The
+    /// old message is decoded before writing, so an error leaves the store
+    /// unchanged.
+    pub fn insert(&mut self, key: &[u8], message: &M) -> Result<Option<M>> {
+        let tree_key = self.tree_key(key);
+        let old = self.decode_stored(&tree_key)?;
+        self.tree.insert(&tree_key, &message.encode_to_vec())?;
+        Ok(old)
+    }
+
+    /// Remove the message under `key`, returning it. As with `insert`, an
+    /// error leaves the store unchanged.
+    pub fn remove(&mut self, key: &[u8]) -> Result<Option<M>> {
+        let tree_key = self.tree_key(key);
+        let old = self.decode_stored(&tree_key)?;
+        self.tree.remove(&tree_key);
+        Ok(old)
+    }
+
+    fn decode_stored(&self, tree_key: &[u8]) -> Result<Option<M>> {
+        match self.tree.get_untracked(tree_key) {
+            Some(bytes) => Ok(Some(M::decode(bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Decoded message together with an existence proof for its encoded bytes.
+    pub fn get_with_proof(&self, key: &[u8]) -> Result<Option<(M, Proof)>> {
+        match self.tree.get_proof(&self.tree_key(key)) {
+            Some(proof) => Ok(Some((M::decode(proof.value.as_slice())?, proof))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> Tree {
+        self.tree
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Clone, PartialEq, Message)]
+    struct Balance {
+        #[prost(string, tag = "1")]
+        denom: String,
+        #[prost(uint64, tag = "2")]
+        amount: u64,
+    }
+
+    #[test]
+    fn test_object_store() {
+        let mut store = ObjectStore::<Balance>::new(Tree::new(), b"balance");
+        let atom = Balance {
+            denom: "atom".to_string(),
+            amount: 10,
+        };
+        assert_eq!(None, store.insert(b"alice", &atom).unwrap());
+        assert_eq!(Some(atom.clone()), store.get(b"alice").unwrap());
+        assert_eq!(None, store.get(b"bob").unwrap());
+
+        let (message, proof) = store.get_with_proof(b"alice").unwrap().unwrap();
+        assert_eq!(atom, message);
+        let tree_key = store.tree_key(b"alice");
+        assert!(store
+            .tree()
+            .verify_existence(&tree_key, &atom.encode_to_vec(), &proof)
+            .is_ok());
+
+        assert_eq!(Some(atom), store.remove(b"alice").unwrap());
+        assert_eq!(None, store.remove(b"alice").unwrap());
+
+        let mut tree = store.into_tree();
+        tree.insert(&tree_key, b"\xff").unwrap();
+        let mut store = ObjectStore::<Balance>::new(tree, b"balance");
+        let osmo = Balance {
+            denom: "osmo".to_string(),
+            amount: 1,
+        };
+        assert!(store.insert(b"alice", &osmo).is_err());
+        assert!(store.remove(b"alice").is_err());
+        assert_eq!(Some(&b"\xff"[..]), store.tree().get(&tree_key));
+    }
+}
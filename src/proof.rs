@@ -1,21 +1,357 @@
-use crate::hash::{hash_array, Hash};
+use std::io::Read;
+
+use crate::codec::{
+    eof_as_malformed, read_array, read_bytes, read_uvarint, write_bytes, write_uvarint,
+};
+use crate::error::{AvlTreeError, Error, Result};
+use crate::hash::{hash_array, leaf_hash, Hash};
+
+/// Header byte of proofs encoded with length-prefixed fields.
+pub const PROOF_FORMAT_V1: u8 = 1;
+
+/// Header byte of proofs encoded with varint lengths and bare 32-byte hash
+/// slots, see `Proof::encode_compact`.
+pub const PROOF_FORMAT_COMPACT: u8 = 2;
+
+const HASH_SLOT_SIZE: usize = 32;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
 pub struct ProofPathNode {
     pub prefix: Vec<u8>,
     pub suffix: Vec<u8>,
 }
 
+#[derive(Eq, PartialEq, Debug, Clone)]
 pub struct Proof {
     pub key: Vec<u8>,
     pub value: Vec<u8>,
     pub path: Vec<ProofPathNode>,
 }
 
+/// Details of a proof whose recomputed root does not match the expected one.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct VerificationReport {
+    pub computed_root: Hash,
+    pub expected_root: Hash,
+    /// Number of path nodes folded into `computed_root`.
+    pub depth: usize,
+    /// Prefix and suffix length of each path node, starting at the leaf.
+    pub path_lengths: Vec<(usize, usize)>,
+}
+
+impl std::fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "computed root {} != expected root {} (depth {}, path lengths {:?})",
+            hex::encode(&self.computed_root),
+            hex::encode(&self.expected_root),
+            self.depth,
+            self.path_lengths
+        )
+    }
+}
+
 impl Proof {
+    /// Check that the proof folds up to `expected_root`, reporting the
+    /// recomputation details on mismatch.
+    pub fn verify_root(&self, expected_root: &[u8]) -> Result<()> {
+        let computed_root = self.calc_root_hash();
+        if computed_root == expected_root {
+            return Ok(());
+        }
+        let report = VerificationReport {
+            computed_root,
+            expected_root: expected_root.to_vec(),
+            depth: self.path.len(),
+            path_lengths: self
+                .path
+                .iter()
+                .map(|node| (node.prefix.len(), node.suffix.len()))
+                .collect(),
+        };
+        Err(AvlTreeError::RootMismatch(Box::new(report)).into())
+    }
+
     pub fn calc_root_hash(&self) -> Hash {
-        let mut hash = hash_array(&[self.key.as_ref(), self.value.as_ref()]);
+        let mut hash = leaf_hash(&self.key, &self.value);
         for node in &self.path {
             hash = hash_array(&[node.prefix.as_ref(), hash.as_ref(), node.suffix.as_ref()])
         }
         hash
     }
+
+    /// Encode the proof, prefixed with its one-byte format header.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![PROOF_FORMAT_V1];
+        self.encode_v1(&mut buf)
+            .expect("[AVL]: Writing to a Vec can not fail");
+        buf
+    }
+
+    /// Decode a proof in any supported format, dispatching on its header.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let (format, mut body) = bytes.split_first().ok_or(AvlTreeError::MalformedProof)?;
+        let proof = match *format {
+            PROOF_FORMAT_V1 => Self::decode_v1(&mut body),
+            PROOF_FORMAT_COMPACT => Self::decode_compact(&mut body),
+            format => return Err(AvlTreeError::UnsupportedProofFormat(format).into()),
+        };
+        let proof = proof.map_err(|err| match err {
+            Error::Tree(AvlTreeError::MalformedEncoding) => AvlTreeError::MalformedProof.into(),
+            err => err,
+        })?;
+        if !body.is_empty() {
+            return Err(AvlTreeError::MalformedProof.into());
+        }
+        Ok(proof)
+    }
+
+    /// Encode with varint lengths and, per path node, one flags byte giving
+    /// the number of 32-byte hash slots in the prefix (low two bits) and
+    /// suffix (next two bits), followed by the bare slots. Fails if a path
+    /// node does not consist of whole hashes.
+    pub fn encode_compact(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![PROOF_FORMAT_COMPACT];
+        write_uvarint(&mut buf, self.key.len() as u64);
+        buf.extend_from_slice(&self.key);
+        write_uvarint(&mut buf, self.value.len() as u64);
+        buf.extend_from_slice(&self.value);
+        write_uvarint(&mut buf, self.path.len() as u64);
+        for node in &self.path {
+            let prefix_slots = hash_slots(&node.prefix)?;
+            let suffix_slots = hash_slots(&node.suffix)?;
+            buf.push(prefix_slots | (suffix_slots << 2));
+            buf.extend_from_slice(&node.prefix);
+            buf.extend_from_slice(&node.suffix);
+        }
+        Ok(buf)
+    }
+
+    fn decode_compact<R: Read>(reader: &mut R) -> Result<Self> {
+        let key = read_varint_bytes(reader)?;
+        let value = read_varint_bytes(reader)?;
+        let len = read_uvarint(reader)?;
+        let mut path = vec![];
+        for _ in 0..len {
+            let [flags] = read_array(reader)?;
+            if flags >> 4 != 0 {
+                return Err(AvlTreeError::MalformedProof.into());
+            }
+            let prefix = read_slots(reader, flags & 0b11)?;
+            let suffix = read_slots(reader, (flags >> 2) & 0b11)?;
+            path.push(ProofPathNode { prefix, suffix });
+        }
+        Ok(Proof { key, value, path })
+    }
+
+    fn encode_v1(&self, buf: &mut Vec<u8>) -> Result<()> {
+        write_bytes(buf, &self.key)?;
+        write_bytes(buf, &self.value)?;
+        buf.extend_from_slice(&(self.path.len() as u32).to_be_bytes());
+        for node in &self.path {
+            write_bytes(buf, &node.prefix)?;
+            write_bytes(buf, &node.suffix)?;
+        }
+        Ok(())
+    }
+
+    fn decode_v1<R: Read>(reader: &mut R) -> Result<Self> {
+        let key = read_bytes(reader)?;
+        let value = read_bytes(reader)?;
+        let len = u32::from_be_bytes(read_array(reader)?);
+        let mut path = vec![];
+        for _ in 0..len {
+            let prefix = read_bytes(reader)?;
+            let suffix = read_bytes(reader)?;
+            path.push(ProofPathNode { prefix, suffix });
+        }
+        Ok(Proof { key, value, path })
+    }
+}
+
+/// Proof that a key held the same value under two roots, e.g. the roots of
+/// two versions of a tree.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct UnchangedValueProof {
+    pub first: Proof,
+    pub second: Proof,
+}
+
+impl UnchangedValueProof {
+    /// Pair two existence proofs, which must be for the same key and value;
+    /// fails with `ProofMismatch` otherwise.
+    pub fn new(first: Proof, second: Proof) -> Result<Self> {
+        if first.key != second.key || first.value != second.value {
+            return Err(AvlTreeError::ProofMismatch.into());
+        }
+        Ok(UnchangedValueProof { first, second })
+    }
+
+    pub fn key(&self) -> &[u8] {
+        &self.first.key
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.first.value
+    }
+
+    /// Check that `key` maps to `value` under both `first_root` and
+    /// `second_root`. Fails with `ProofMismatch` if the proofs are for
+    /// another key or value, before checking either root.
+    pub fn verify(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        first_root: &[u8],
+        second_root: &[u8],
+    ) -> Result<()> {
+        for proof in [&self.first, &self.second] {
+            if proof.key != key || proof.value != value {
+                return Err(AvlTreeError::ProofMismatch.into());
+            }
+        }
+        self.first.verify_root(first_root)?;
+        self.second.verify_root(second_root)
+    }
+}
+
+fn hash_slots(bytes: &[u8]) -> Result<u8> {
+    match bytes.len() {
+        0 => Ok(0),
+        HASH_SLOT_SIZE => Ok(1),
+        len if len == 2 * HASH_SLOT_SIZE => Ok(2),
+        _ => Err(AvlTreeError::MalformedProof.into()),
+    }
+}
+
+fn read_slots<R: Read>(reader: &mut R, slots: u8) -> Result<Vec<u8>> {
+    if slots > 2 {
+        return Err(AvlTreeError::MalformedProof.into());
+    }
+    let mut buf = vec![0u8; slots as usize * HASH_SLOT_SIZE];
+    reader.read_exact(&mut buf).map_err(eof_as_malformed)?;
+    Ok(buf)
+}
+
+fn read_varint_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let len = read_uvarint(reader)?;
+    let mut buf = vec![];
+    reader.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(AvlTreeError::MalformedProof.into());
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::{Error, ErrorKind};
+    use crate::tree::Tree;
+
+    #[test]
+    fn test_encode_decode() {
+        let mut tree = Tree::new();
+        for i in 0u32..100u32 {
+            tree.insert(&i.to_le_bytes(), &i.to_le_bytes()).unwrap();
+        }
+        let proof = tree.get_proof(&42u32.to_le_bytes()).unwrap();
+        let bytes = proof.encode();
+        assert_eq!(PROOF_FORMAT_V1, bytes[0]);
+        assert_eq!(proof, Proof::decode(&bytes).unwrap());
+
+        let mut unknown = bytes.clone();
+        unknown[0] = 0xff;
+        assert!(Proof::decode(&unknown).is_err());
+        let err = Proof::decode(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(ErrorKind::InvalidProof, err.kind());
+        assert!(Proof::decode(&[]).is_err());
+    }
+
+    #[test]
+    fn test_mismatch_report() {
+        let mut tree = Tree::new();
+        for i in 0u32..100u32 {
+            tree.insert(&i.to_le_bytes(), &i.to_le_bytes()).unwrap();
+        }
+        let mut proof = tree.get_proof(&7u32.to_le_bytes()).unwrap();
+        assert!(proof.verify_root(tree.root_hash().unwrap()).is_ok());
+
+        proof.path[0].suffix.push(0);
+        let err = proof.verify_root(tree.root_hash().unwrap()).unwrap_err();
+        match err {
+            Error::Tree(AvlTreeError::RootMismatch(report)) => {
+                assert_eq!(tree.root_hash().unwrap(), &report.expected_root);
+                assert_eq!(proof.calc_root_hash(), report.computed_root);
+                assert_eq!(proof.path.len(), report.depth);
+                assert_eq!(proof.path[0].suffix.len(), report.path_lengths[0].1);
+            }
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn test_unchanged_value_proof() {
+        let mut first = Tree::new();
+        for i in 0u32..100u32 {
+            first.insert(&i.to_le_bytes(), &i.to_le_bytes()).unwrap();
+        }
+        let mut second = first.clone();
+        second.insert(&3u32.to_le_bytes(), b"changed").unwrap();
+        second.insert(&200u32.to_le_bytes(), b"new").unwrap();
+        let (first_root, second_root) = (first.root_hash().unwrap(), second.root_hash().unwrap());
+
+        let key = 7u32.to_le_bytes();
+        let proof = UnchangedValueProof::new(
+            first.get_proof(&key).unwrap(),
+            second.get_proof(&key).unwrap(),
+        )
+        .unwrap();
+        assert!(proof.verify(&key, &key, first_root, second_root).is_ok());
+        let err = proof
+            .verify(&key, &key, second_root, first_root)
+            .unwrap_err();
+        assert_eq!(ErrorKind::InvalidProof, err.kind());
+        let err = proof
+            .verify(&key, b"other", first_root, second_root)
+            .unwrap_err();
+        assert!(matches!(err, Error::Tree(AvlTreeError::ProofMismatch)));
+
+        let key = 3u32.to_le_bytes();
+        let err = UnchangedValueProof::new(
+            first.get_proof(&key).unwrap(),
+            second.get_proof(&key).unwrap(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Tree(AvlTreeError::ProofMismatch)));
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+    }
+
+    #[test]
+    fn test_compact_encoding() {
+        let mut tree = Tree::new();
+        for i in 0u32..1000u32 {
+            tree.insert(&i.to_le_bytes(), &i.to_le_bytes()).unwrap();
+        }
+        for i in [0u32, 1, 500, 999] {
+            let proof = tree.get_proof(&i.to_le_bytes()).unwrap();
+            let compact = proof.encode_compact().unwrap();
+            assert_eq!(PROOF_FORMAT_COMPACT, compact[0]);
+            assert!(compact.len() < proof.encode().len());
+            assert_eq!(proof, Proof::decode(&compact).unwrap());
+            let err = Proof::decode(&compact[..compact.len() - 1]).unwrap_err();
+            assert_eq!(ErrorKind::InvalidProof, err.kind());
+        }
+
+        let odd = Proof {
+            key: b"key".to_vec(),
+            value: b"value".to_vec(),
+            path: vec![ProofPathNode {
+                prefix: vec![1; 3],
+                suffix: vec![],
+            }],
+        };
+        assert!(odd.encode_compact().is_err());
+    }
 }
@@ -0,0 +1,451 @@
+use std::cmp::Ordering;
+
+use crate::node::{Node, NodeRef};
+use crate::tree::{BalancePolicy, KeyComparator, WEIGHT_DELTA, WEIGHT_GAMMA};
+
+/// The heights and sizes a write would leave in a subtree, worked out with
+/// the same joins and rotations the tree performs but without touching it.
+/// Subtrees the write does not reach are borrowed as they are, so a dry run
+/// costs as much as the write itself rather than a copy of the tree.
+pub(crate) enum Shape<'a> {
+    Empty,
+    /// A subtree of the tree being written, not yet reached.
+    Real(&'a Node),
+    /// The subtree `Tree::build_balanced` makes from this many new entries.
+    Balanced(usize),
+    Open(Box<Open<'a>>),
+}
+
+/// A node the dry run has reached. Only new entries have no `key`, and no
+/// dry run splits those.
+pub(crate) struct Open<'a> {
+    key: Option<&'a [u8]>,
+    left: Shape<'a>,
+    right: Shape<'a>,
+    height: i64,
+    size: usize,
+}
+
+impl<'a> Shape<'a> {
+    pub(crate) fn of(node_ref: &'a NodeRef) -> Self {
+        match node_ref {
+            Some(node) => Shape::Real(node),
+            None => Shape::Empty,
+        }
+    }
+
+    /// Height as `Node::height` records it, counting an empty subtree as -1.
+    pub(crate) fn height(&self) -> i64 {
+        match self {
+            Shape::Empty | Shape::Balanced(0) => -1,
+            Shape::Real(node) => node.height as i64,
+            Shape::Balanced(len) => len.ilog2() as i64,
+            Shape::Open(open) => open.height,
+        }
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        match self {
+            Shape::Empty => 0,
+            Shape::Real(node) => node.size,
+            Shape::Balanced(len) => *len,
+            Shape::Open(open) => open.size,
+        }
+    }
+
+    #[cfg(test)]
+    fn height_and_size(&self) -> (i64, usize) {
+        (self.height(), self.size())
+    }
+
+    fn weight(&self) -> usize {
+        self.size() + 1
+    }
+
+    fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+
+    /// Height as `Node::balance_factor` counts it, where a missing child
+    /// weighs as much as a leaf.
+    fn balance_height(&self) -> i64 {
+        std::cmp::max(self.height(), 0)
+    }
+
+    fn open(self) -> Box<Open<'a>> {
+        match self {
+            Shape::Real(node) => Box::new(Open {
+                key: Some(node.key.as_slice()),
+                left: Shape::of(&node.left),
+                right: Shape::of(&node.right),
+                height: node.height as i64,
+                size: node.size,
+            }),
+            Shape::Balanced(len) if len > 0 => {
+                let left = Shape::Balanced(len / 2);
+                let right = Shape::Balanced(len - len / 2 - 1);
+                Open::new(None, left, right)
+            }
+            Shape::Open(open) => open,
+            _ => panic!("[AVL]: Opening an empty subtree"),
+        }
+    }
+}
+
+impl<'a> Open<'a> {
+    fn new(key: Option<&'a [u8]>, left: Shape<'a>, right: Shape<'a>) -> Box<Self> {
+        let mut open = Box::new(Open {
+            key,
+            left,
+            right,
+            height: 0,
+            size: 0,
+        });
+        open.update();
+        open
+    }
+
+    fn update(&mut self) {
+        self.height = std::cmp::max(self.left.height(), self.right.height()) + 1;
+        self.size = 1 + self.left.size() + self.right.size();
+    }
+
+    fn balance_factor(&self) -> i64 {
+        self.left.balance_height() - self.right.balance_height()
+    }
+}
+
+fn too_heavy(policy: BalancePolicy, heavy: &Shape, light: &Shape) -> bool {
+    match policy {
+        BalancePolicy::Height => heavy.height() > light.height() + 1,
+        BalancePolicy::Weight => heavy.weight() > WEIGHT_DELTA * light.weight(),
+    }
+}
+
+/// Mirrors `Tree::balance_node`.
+fn balance(policy: BalancePolicy, mut node: Box<Open<'_>>) -> Shape<'_> {
+    let (left_heavy, right_heavy) = match policy {
+        BalancePolicy::Height => {
+            let balance_factor = node.balance_factor();
+            (balance_factor >= 2, balance_factor <= -2)
+        }
+        BalancePolicy::Weight => (
+            too_heavy(policy, &node.left, &node.right),
+            too_heavy(policy, &node.right, &node.left),
+        ),
+    };
+    if left_heavy {
+        let left = std::mem::replace(&mut node.left, Shape::Empty).open();
+        let inner_heavy = match policy {
+            BalancePolicy::Height => left.balance_factor() < 0,
+            BalancePolicy::Weight => left.right.weight() >= WEIGHT_GAMMA * left.left.weight(),
+        };
+        node.left = if inner_heavy {
+            rotate_left(left)
+        } else {
+            Shape::Open(left)
+        };
+        rotate_right(node)
+    } else if right_heavy {
+        let right = std::mem::replace(&mut node.right, Shape::Empty).open();
+        let inner_heavy = match policy {
+            BalancePolicy::Height => right.balance_factor() > 0,
+            BalancePolicy::Weight => right.left.weight() >= WEIGHT_GAMMA * right.right.weight(),
+        };
+        node.right = if inner_heavy {
+            rotate_right(right)
+        } else {
+            Shape::Open(right)
+        };
+        rotate_left(node)
+    } else {
+        Shape::Open(node)
+    }
+}
+
+fn rotate_right(mut node: Box<Open<'_>>) -> Shape<'_> {
+    let mut left = std::mem::replace(&mut node.left, Shape::Empty).open();
+    node.left = std::mem::replace(&mut left.right, Shape::Empty);
+    node.update();
+    left.right = Shape::Open(node);
+    left.update();
+    Shape::Open(left)
+}
+
+fn rotate_left(mut node: Box<Open<'_>>) -> Shape<'_> {
+    let mut right = std::mem::replace(&mut node.right, Shape::Empty).open();
+    node.right = std::mem::replace(&mut right.left, Shape::Empty);
+    node.update();
+    right.left = Shape::Open(node);
+    right.update();
+    Shape::Open(right)
+}
+
+/// Mirrors `Tree::join`.
+fn join<'a>(
+    policy: BalancePolicy,
+    left: Shape<'a>,
+    key: Option<&'a [u8]>,
+    right: Shape<'a>,
+) -> Shape<'a> {
+    if too_heavy(policy, &left, &right) {
+        let mut left = left.open();
+        let inner = std::mem::replace(&mut left.right, Shape::Empty);
+        left.right = join(policy, inner, key, right);
+        left.update();
+        balance(policy, left)
+    } else if too_heavy(policy, &right, &left) {
+        let mut right = right.open();
+        let inner = std::mem::replace(&mut right.left, Shape::Empty);
+        right.left = join(policy, left, key, inner);
+        right.update();
+        balance(policy, right)
+    } else {
+        Shape::Open(Open::new(key, left, right))
+    }
+}
+
+/// Mirrors `Tree::insert_iterative` for a key the subtree does not hold.
+pub(crate) fn insert<'a>(
+    policy: BalancePolicy,
+    compare: KeyComparator,
+    mut shape: Shape<'a>,
+    key: &'a [u8],
+) -> Shape<'a> {
+    let mut path = vec![];
+    while !shape.is_empty() {
+        let mut node = shape.open();
+        let node_key = node.key.expect("[AVL]: Inserting below a new entry");
+        let ordering = compare(node_key, key);
+        shape = match ordering {
+            Ordering::Greater => std::mem::replace(&mut node.left, Shape::Empty),
+            Ordering::Less => std::mem::replace(&mut node.right, Shape::Empty),
+            Ordering::Equal => panic!("[AVL]: Dry run of an overwrite"),
+        };
+        path.push((node, ordering));
+    }
+    let mut subtree = Shape::Open(Open::new(Some(key), Shape::Empty, Shape::Empty));
+    while let Some((mut node, ordering)) = path.pop() {
+        if ordering == Ordering::Greater {
+            node.left = subtree;
+        } else {
+            node.right = subtree;
+        }
+        node.update();
+        subtree = balance(policy, node);
+    }
+    subtree
+}
+
+/// Mirrors `Tree::concat`.
+pub(crate) fn concat<'a>(policy: BalancePolicy, left: Shape<'a>, right: Shape<'a>) -> Shape<'a> {
+    if right.is_empty() {
+        return left;
+    }
+    let (key, right) = remove_min(policy, right);
+    join(policy, left, key, right)
+}
+
+/// Mirrors `Tree::remove_min`, returning the removed key and what is left.
+fn remove_min(policy: BalancePolicy, shape: Shape<'_>) -> (Option<&[u8]>, Shape<'_>) {
+    let mut node = shape.open();
+    if node.left.is_empty() {
+        return (node.key, std::mem::replace(&mut node.right, Shape::Empty));
+    }
+    let (key, left) = remove_min(policy, std::mem::replace(&mut node.left, Shape::Empty));
+    node.left = left;
+    node.update();
+    (key, balance(policy, node))
+}
+
+/// Mirrors `Tree::split`, dropping the node holding `key`.
+fn split<'a>(
+    policy: BalancePolicy,
+    compare: KeyComparator,
+    shape: Shape<'a>,
+    key: &[u8],
+) -> (Shape<'a>, Shape<'a>) {
+    if shape.is_empty() {
+        return (Shape::Empty, Shape::Empty);
+    }
+    let mut node = shape.open();
+    let left = std::mem::replace(&mut node.left, Shape::Empty);
+    let right = std::mem::replace(&mut node.right, Shape::Empty);
+    let node_key = node.key.expect("[AVL]: Splitting a new entry");
+    match compare(node_key, key) {
+        Ordering::Equal => (left, right),
+        Ordering::Greater => {
+            let (less, greater) = split(policy, compare, left, key);
+            (less, join(policy, greater, node.key, right))
+        }
+        Ordering::Less => {
+            let (less, greater) = split(policy, compare, right, key);
+            (join(policy, left, node.key, less), greater)
+        }
+    }
+}
+
+/// Mirrors `Tree::union`.
+pub(crate) fn union<'a>(
+    policy: BalancePolicy,
+    compare: KeyComparator,
+    left: Shape<'a>,
+    right: Shape<'a>,
+) -> Shape<'a> {
+    if right.is_empty() {
+        return left;
+    }
+    if left.is_empty() {
+        return right;
+    }
+    let mut node = right.open();
+    let key = node.key.expect("[AVL]: Splitting at a new entry");
+    let (less, greater) = split(policy, compare, left, key);
+    let left = union(
+        policy,
+        compare,
+        less,
+        std::mem::replace(&mut node.left, Shape::Empty),
+    );
+    let right = union(
+        policy,
+        compare,
+        greater,
+        std::mem::replace(&mut node.right, Shape::Empty),
+    );
+    join(policy, left, node.key, right)
+}
+
+/// Mirrors `Tree::apply_batch_recursive`.
+pub(crate) fn batch<'a>(
+    policy: BalancePolicy,
+    compare: KeyComparator,
+    shape: Shape<'a>,
+    writes: &[(&[u8], Option<&[u8]>)],
+) -> Shape<'a> {
+    if shape.is_empty() {
+        return Shape::Balanced(writes.iter().filter(|(_, value)| value.is_some()).count());
+    }
+    if writes.is_empty() {
+        return shape;
+    }
+    let mut node = shape.open();
+    let node_key = node.key.expect("[AVL]: Writing below a new entry");
+    let split = writes.partition_point(|(key, _)| compare(key, node_key) == Ordering::Less);
+    let found = writes.get(split).filter(|(key, _)| *key == node_key);
+    let left_writes = &writes[..split];
+    let right_writes = &writes[split + found.is_some() as usize..];
+    let left = std::mem::replace(&mut node.left, Shape::Empty);
+    let right = std::mem::replace(&mut node.right, Shape::Empty);
+    let left = batch(policy, compare, left, left_writes);
+    let right = batch(policy, compare, right, right_writes);
+    match found {
+        Some((_, None)) => concat(policy, left, right),
+        _ => join(policy, left, node.key, right),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::{Op, Tree};
+
+    fn next(x: &mut u64) -> u64 {
+        *x ^= *x << 13;
+        *x ^= *x >> 7;
+        *x ^= *x << 17;
+        *x
+    }
+
+    fn random_tree(policy: BalancePolicy, x: &mut u64, len: u64, space: u64) -> Tree {
+        let mut tree = Tree::new().with_balance_policy(policy);
+        for _ in 0..len {
+            let key = (next(x) % space).to_be_bytes();
+            tree.insert(&key, &key).unwrap();
+        }
+        tree
+    }
+
+    fn measure(tree: &Tree) -> (i64, usize) {
+        Shape::of(&tree.root).height_and_size()
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut x = 3u64;
+        for policy in [BalancePolicy::Height, BalancePolicy::Weight] {
+            let mut tree = Tree::new().with_balance_policy(policy);
+            for _ in 0..2000 {
+                let key = (next(&mut x) % 1000).to_be_bytes();
+                if tree.contains_key(&key) {
+                    continue;
+                }
+                let shape = insert(policy, tree.key_cmp(), Shape::of(&tree.root), &key);
+                let predicted = shape.height_and_size();
+                tree.insert(&key, &key).unwrap();
+                assert_eq!(predicted, measure(&tree));
+            }
+        }
+    }
+
+    #[test]
+    fn test_batch() {
+        let mut x = 7u64;
+        for policy in [BalancePolicy::Height, BalancePolicy::Weight] {
+            for round in 0..100u64 {
+                let space = [50, 1000][(round % 2) as usize];
+                let mut tree = random_tree(policy, &mut x, round * 3, space);
+                let mut keys: Vec<[u8; 8]> = (0..next(&mut x) % 64)
+                    .map(|_| (next(&mut x) % space).to_be_bytes())
+                    .collect();
+                keys.sort();
+                keys.dedup();
+                let writes: Vec<(&[u8], Option<&[u8]>)> = keys
+                    .iter()
+                    .map(|key| {
+                        (
+                            &key[..],
+                            (!next(&mut x).is_multiple_of(3)).then_some(&key[..]),
+                        )
+                    })
+                    .collect();
+                let ops: Vec<Op> = writes
+                    .iter()
+                    .map(|(key, value)| match value {
+                        Some(value) => Op::Set(key.to_vec(), value.to_vec()),
+                        None => Op::Delete(key.to_vec()),
+                    })
+                    .collect();
+                let before = tree.clone();
+                let shape = batch(policy, before.key_cmp(), Shape::of(&before.root), &writes);
+                tree.apply_batch(&ops).unwrap();
+                assert_eq!(shape.height_and_size(), measure(&tree));
+            }
+        }
+    }
+
+    #[test]
+    fn test_union_and_concat() {
+        let mut x = 11u64;
+        for policy in [BalancePolicy::Height, BalancePolicy::Weight] {
+            for round in 0..100u64 {
+                let mut tree = random_tree(policy, &mut x, round * 2, 1000);
+                let disjoint = round % 2 == 0;
+                let mut other = Tree::new().with_balance_policy(policy);
+                for _ in 0..next(&mut x) % 300 {
+                    let key = next(&mut x) % 1000 + if disjoint { 1000 } else { 0 };
+                    other.insert(&key.to_be_bytes(), b"").unwrap();
+                }
+                let (root, other_root) = (Shape::of(&tree.root), Shape::of(&other.root));
+                let shape = if disjoint {
+                    concat(policy, root, other_root)
+                } else {
+                    union(policy, tree.key_cmp(), root, other_root)
+                };
+                let predicted = shape.height_and_size();
+                tree.append(other).unwrap();
+                assert_eq!(predicted, measure(&tree));
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::num::NonZeroUsize;
+
+use crate::error::Result;
+use crate::hash::{hash_array, hash_many, hash_value, Hash};
+use crate::tree::Tree;
+
+/// Keys partitioned by hash across independent subtrees. Batches are applied
+/// to the shards in parallel and the combined root commits to every shard
+/// root in shard order.
+pub struct ShardedTree {
+    shards: Vec<Tree>,
+}
+
+impl ShardedTree {
+    pub fn new(shard_count: NonZeroUsize) -> Self {
+        ShardedTree {
+            shards: (0..shard_count.get()).map(|_| Tree::new()).collect(),
+        }
+    }
+
+    pub fn shard_for(&self, key: &[u8]) -> usize {
+        let hash = hash_value(key);
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&hash[..8]);
+        (u64::from_be_bytes(prefix) % self.shards.len() as u64) as usize
+    }
+
+    pub fn shard(&self, index: usize) -> &Tree {
+        &self.shards[index]
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.shards[self.shard_for(key)].get(key)
+    }
+
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        let shard = self.shard_for(key);
+        self.shards[shard].insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let shard = self.shard_for(key);
+        self.shards[shard].remove(key)
+    }
+
+    /// Insert `entries`, one worker thread per shard. Entries for the same
+    /// key are applied in their original order. Every entry is checked as
+    /// `Tree::insert` would before any shard is written, so a rejected entry
+    /// leaves all shards unchanged.
+    pub fn insert_batch(&mut self, entries: &[(&[u8], &[u8])]) -> Result<()> {
+        for &(key, value) in entries {
+            self.shards[self.shard_for(key)].validate_entry(key, value)?;
+        }
+        let mut per_shard: Vec<Vec<(&[u8], &[u8])>> = vec![vec![]; self.shards.len()];
+        for &(key, value) in entries {
+            per_shard[self.shard_for(key)].push((key, value));
+        }
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = self
+                .shards
+                .iter_mut()
+                .zip(per_shard)
+                .filter(|(_, entries)| !entries.is_empty())
+                .map(|(shard, entries)| {
+                    scope.spawn(move || -> Result<()> {
+                        for (key, value) in entries {
+                            shard.insert(key, value)?;
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().expect("[AVL]: Shard worker panicked"))
+        })
+    }
+
+    /// Root hash of each shard; empty shards have an empty root.
+    pub fn shard_roots(&self) -> Vec<&[u8]> {
+        self.shards
+            .iter()
+            .map(|shard| shard.root_hash().map_or(&[][..], |h| h.as_ref()))
+            .collect()
+    }
+
+    /// Commitment over all shard roots, each hashed so that empty shards
+    /// still occupy a fixed-size slot.
+    pub fn root_hash(&self) -> Hash {
+        let roots = self.shard_roots();
+        let preimages: Vec<[&[u8]; 1]> = roots.into_iter().map(|root| [root]).collect();
+        let preimages: Vec<&[&[u8]]> = preimages.iter().map(|p| &p[..]).collect();
+        let slots = hash_many(&preimages);
+        let slots: Vec<&[u8]> = slots.iter().map(|s| s.as_ref()).collect();
+        hash_array(&slots)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sharded_tree() {
+        let keys: Vec<[u8; 4]> = (0u32..1000u32).map(|i| i.to_le_bytes()).collect();
+        let entries: Vec<(&[u8], &[u8])> = keys.iter().map(|k| (&k[..], &k[..])).collect();
+
+        let shard_count = NonZeroUsize::new(4).unwrap();
+        let mut parallel = ShardedTree::new(shard_count);
+        parallel.insert_batch(&entries).unwrap();
+        let mut sequential = ShardedTree::new(shard_count);
+        for (key, value) in &entries {
+            sequential.insert(key, value).unwrap();
+        }
+        assert_eq!(parallel.root_hash(), sequential.root_hash());
+        for key in &keys {
+            assert_eq!(Some(&key[..]), parallel.get(key));
+        }
+
+        let before = parallel.root_hash();
+        parallel.remove(&keys[0]);
+        assert_eq!(None, parallel.get(&keys[0]));
+        assert_ne!(before, parallel.root_hash());
+        let before = parallel.root_hash();
+        let batch: [(&[u8], &[u8]); 3] = [(b"x", b"1"), (b"", b"value"), (b"y", b"2")];
+        assert!(parallel.insert_batch(&batch).is_err());
+        assert_eq!(before, parallel.root_hash());
+        assert_eq!(None, parallel.get(b"x"));
+    }
+}
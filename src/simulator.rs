@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+
+use crate::db::DB;
+use crate::error::{AvlTreeError, Result};
+use crate::hash::Hash;
+use crate::keys::encode_u64;
+use crate::tree::Tree;
+use crate::visit::TraversalOrder;
+
+/// How the simulator picks the key of each operation.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum KeyDistribution {
+    Uniform,
+    /// Keys `0, 1, 2, ...` wrapping around at the key space.
+    Sequential,
+    /// `hot_percent`% of operations go to the first `hot_keys` keys.
+    Hotspot {
+        hot_keys: u64,
+        hot_percent: u8,
+    },
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Workload {
+    pub seed: u64,
+    pub ops: u64,
+    /// Number of distinct keys the workload draws from.
+    pub key_space: u64,
+    pub distribution: KeyDistribution,
+    /// Relative weights of writes, reads and deletes.
+    pub write_weight: u32,
+    pub read_weight: u32,
+    pub delete_weight: u32,
+    pub min_value_size: usize,
+    pub max_value_size: usize,
+    /// Check invariants every this many operations; `0` only checks at the end.
+    pub check_every: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Workload {
+            seed: 1,
+            ops: 10_000,
+            key_space: 1_000,
+            distribution: KeyDistribution::Uniform,
+            write_weight: 50,
+            read_weight: 35,
+            delete_weight: 15,
+            min_value_size: 0,
+            max_value_size: 64,
+            check_every: 1_000,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct SimulationReport {
+    pub writes: u64,
+    pub reads: u64,
+    pub deletes: u64,
+    pub checks: u64,
+    pub size: usize,
+    pub root_hash: Option<Hash>,
+}
+
+/// Key under which `simulate_on` persists the tree at each check.
+const SNAPSHOT_KEY: &[u8] = b"simulator/tree";
+
+/// Run `workload` against an in-memory tree, mirroring every operation into
+/// a `BTreeMap` model and a replica tree. Each check compares the tree with
+/// the model, verifies its structure and a proof, requires the replica to
+/// have reached the same root hash, and requires a serialize/deserialize
+/// round trip to reproduce the tree.
+pub fn simulate(workload: &Workload) -> Result<SimulationReport> {
+    simulate_with(workload, None)
+}
+
+/// Like `simulate`, but each check also persists the serialized tree to `db`
+/// and reloads it from there, so divergence in the storage layer shows up.
+pub fn simulate_on(workload: &Workload, db: &mut dyn DB) -> Result<SimulationReport> {
+    simulate_with(workload, Some(db))
+}
+
+fn simulate_with(workload: &Workload, mut db: Option<&mut dyn DB>) -> Result<SimulationReport> {
+    let mut rng = XorShift::new(workload.seed);
+    let mut tree = Tree::new();
+    let mut replica = Tree::new();
+    let mut model = BTreeMap::new();
+    let mut report = SimulationReport::default();
+    let total_weight = workload
+        .write_weight
+        .checked_add(workload.read_weight)
+        .and_then(|weight| weight.checked_add(workload.delete_weight))
+        .ok_or_else(|| violation("workload weights overflow"))?;
+    if total_weight == 0 {
+        return Err(violation("workload has no operations weighted"));
+    }
+
+    for op in 0..workload.ops {
+        let key = encode_u64(pick_key(workload, &mut rng, op)).to_vec();
+        let roll = rng.below(total_weight as u64) as u32;
+        if roll < workload.write_weight {
+            let value = random_value(workload, &mut rng);
+            let old = tree.insert(&key, &value)?;
+            replica.insert(&key, &value)?;
+            if old != model.insert(key, value) {
+                return Err(violation("insert returned a stale previous value"));
+            }
+            report.writes += 1;
+        } else if roll < workload.write_weight + workload.read_weight {
+            if tree.get(&key) != model.get(&key).map(Vec::as_slice) {
+                return Err(violation("get disagrees with the model"));
+            }
+            report.reads += 1;
+        } else {
+            let old = tree.remove(&key);
+            replica.remove(&key);
+            if old != model.remove(&key) {
+                return Err(violation("remove returned a stale previous value"));
+            }
+            report.deletes += 1;
+        }
+
+        if workload.check_every != 0 && (op + 1) % workload.check_every == 0 {
+            check(&tree, &replica, &model, &mut rng, &mut db)?;
+            report.checks += 1;
+        }
+    }
+    check(&tree, &replica, &model, &mut rng, &mut db)?;
+    report.checks += 1;
+
+    report.size = model.len();
+    report.root_hash = tree.root_hash().cloned();
+    Ok(report)
+}
+
+fn check(
+    tree: &Tree,
+    replica: &Tree,
+    model: &BTreeMap<Vec<u8>, Vec<u8>>,
+    rng: &mut XorShift,
+    db: &mut Option<&mut dyn DB>,
+) -> Result<()> {
+    if tree.root_hash() != replica.root_hash() {
+        return Err(violation(
+            "replaying the same operations gave a different root",
+        ));
+    }
+
+    let mut bytes = vec![];
+    tree.serialize(&mut bytes)?;
+    if let Some(db) = db {
+        db.set(SNAPSHOT_KEY, &bytes)?;
+        bytes = db
+            .get(SNAPSHOT_KEY)?
+            .ok_or_else(|| violation("persisted tree is missing"))?;
+    }
+    if Tree::deserialize(&mut bytes.as_slice())? != *tree {
+        return Err(violation("reloading the tree did not reproduce it"));
+    }
+
+    let mut entries = vec![];
+    let mut max_depth = 0;
+    tree.visit_nodes(TraversalOrder::InOrder, |info| {
+        entries.push((info.key.to_vec(), info.value.to_vec()));
+        max_depth = max_depth.max(info.depth as u32);
+    });
+    let model_entries = model.iter().map(|(k, v)| (k.as_slice(), v.as_slice()));
+    if !entries
+        .iter()
+        .map(|(k, v)| (k.as_slice(), v.as_slice()))
+        .eq(model_entries)
+    {
+        return Err(violation("tree contents disagree with the model"));
+    }
+    if let Some(root) = &tree.root {
+        if root.size != model.len() || root.height != max_depth {
+            return Err(violation("root size or height is stale"));
+        }
+        let (key, value) = &entries[rng.below(entries.len() as u64) as usize];
+        let proof = tree
+            .get_proof(key)
+            .ok_or_else(|| violation("no proof for a present key"))?;
+        tree.verify_existence(key, value, &proof)?;
+    }
+    Ok(())
+}
+
+fn pick_key(workload: &Workload, rng: &mut XorShift, op: u64) -> u64 {
+    let key_space = workload.key_space.max(1);
+    match workload.distribution {
+        KeyDistribution::Uniform => rng.below(key_space),
+        KeyDistribution::Sequential => op % key_space,
+        KeyDistribution::Hotspot {
+            hot_keys,
+            hot_percent,
+        } => {
+            let hot_keys = hot_keys.clamp(1, key_space);
+            if rng.below(100) < hot_percent as u64 {
+                rng.below(hot_keys)
+            } else {
+                rng.below(key_space)
+            }
+        }
+    }
+}
+
+fn random_value(workload: &Workload, rng: &mut XorShift) -> Vec<u8> {
+    let spread = workload
+        .max_value_size
+        .saturating_sub(workload.min_value_size) as u64;
+    let len = workload.min_value_size + rng.below(spread + 1) as usize;
+    (0..len).map(|_| rng.next() as u8).collect()
+}
+
+fn violation(message: &str) -> crate::error::Error {
+    AvlTreeError::InvariantViolation(message.to_string()).into()
+}
+
+/// xorshift64* — small, fast and fully determined by its seed.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // The all-zero state is a fixed point.
+        XorShift(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::new_rocks_db;
+
+    #[test]
+    fn test_simulate() {
+        let workload = Workload {
+            ops: 3_000,
+            key_space: 200,
+            check_every: 500,
+            ..Workload::default()
+        };
+        let report = simulate(&workload).unwrap();
+        assert_eq!(workload.ops, report.writes + report.reads + report.deletes);
+        assert_eq!(7, report.checks);
+        assert_eq!(report, simulate(&workload).unwrap());
+
+        let reseeded = simulate(&Workload {
+            seed: 2,
+            ..workload.clone()
+        })
+        .unwrap();
+        assert_ne!(report.root_hash, reseeded.root_hash);
+
+        for distribution in [
+            KeyDistribution::Sequential,
+            KeyDistribution::Hotspot {
+                hot_keys: 10,
+                hot_percent: 90,
+            },
+        ] {
+            let workload = Workload {
+                distribution,
+                ..workload.clone()
+            };
+            assert!(simulate(&workload).is_ok());
+        }
+
+        let dir = std::env::temp_dir();
+        let mut db = new_rocks_db("test_simulate_on", &dir).unwrap();
+        assert_eq!(report, simulate_on(&workload, &mut db).unwrap());
+        drop(db);
+        std::fs::remove_dir_all(dir.join("test_simulate_on.db")).unwrap();
+
+        let overflowing = Workload {
+            write_weight: u32::MAX,
+            ..workload
+        };
+        assert!(simulate(&overflowing).is_err());
+    }
+}
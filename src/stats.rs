@@ -0,0 +1,108 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Point-in-time copy of a tree's operation counters.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct TreeStats {
+    pub gets: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub sets: u64,
+    pub deletes: u64,
+    pub proofs: u64,
+    pub nodes_hashed: u64,
+}
+
+/// Point-in-time RocksDB block cache, bloom filter and memtable figures.
+/// Ticker counts are cumulative since the database was opened.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct DBStats {
+    pub block_cache_hits: u64,
+    pub block_cache_misses: u64,
+    pub block_cache_usage: u64,
+    /// Point lookups the bloom filter answered without reading a block.
+    pub bloom_filter_useful: u64,
+    /// Lookups the bloom filter passed that did find the key.
+    pub bloom_filter_true_positive: u64,
+    pub memtable_bytes: u64,
+    pub memtable_entries: u64,
+}
+
+/// Always-on counters updated with relaxed atomics. They are observational
+/// only: cloning copies the current values.
+#[derive(Debug, Default)]
+pub struct Counters {
+    gets: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    sets: AtomicU64,
+    deletes: AtomicU64,
+    proofs: AtomicU64,
+    nodes_hashed: AtomicU64,
+}
+
+impl Counters {
+    pub fn record_get(&self, hit: bool) {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+        if hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_set(&self) {
+        self.sets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_delete(&self) {
+        self.deletes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_proof(&self) {
+        self.proofs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_nodes_hashed(&self, count: u64) {
+        self.nodes_hashed.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> TreeStats {
+        TreeStats {
+            gets: self.gets.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            sets: self.sets.load(Ordering::Relaxed),
+            deletes: self.deletes.load(Ordering::Relaxed),
+            proofs: self.proofs.load(Ordering::Relaxed),
+            nodes_hashed: self.nodes_hashed.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset(&self) {
+        for counter in self.counters() {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn counters(&self) -> [&AtomicU64; 7] {
+        [
+            &self.gets,
+            &self.hits,
+            &self.misses,
+            &self.sets,
+            &self.deletes,
+            &self.proofs,
+            &self.nodes_hashed,
+        ]
+    }
+}
+
+impl Clone for Counters {
+    fn clone(&self) -> Self {
+        let counters = Counters::default();
+        for (dst, src) in counters.counters().iter().zip(self.counters()) {
+            dst.store(src.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        counters
+    }
+}
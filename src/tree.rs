@@ -1,29 +1,242 @@
-use crate::error::AvlTreeError;
+use crate::codec::{read_array, read_bytes, write_bytes};
+use crate::error::{AvlTreeError, Result};
 use crate::hash::*;
 use crate::node::*;
 use crate::proof::*;
-use anyhow::*;
+use crate::shape::{self, Shape};
+use crate::stats::{Counters, TreeStats};
 use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::ops::Bound;
 
+/// Largest key accepted by `Tree::insert`.
+pub const MAX_KEY_SIZE: usize = 64 * 1024;
+
+/// Largest value accepted by `Tree::insert`.
+pub const MAX_VALUE_SIZE: usize = 64 * 1024 * 1024;
+
+/// One page of entries returned by `Tree::paginate`.
 #[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Page {
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Cursor for the following page, or `None` when this page is the last.
+    pub next_cursor: Option<Vec<u8>>,
+}
+
+/// A single write, as applied by `Tree::apply`.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Op {
+    Set(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+impl Op {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Op::Set(key, _) | Op::Delete(key) => key,
+        }
+    }
+}
+
+/// How a tree keeps itself balanced. The policy changes the tree's shape,
+/// and so its root hash, but not how nodes are hashed.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum BalancePolicy {
+    /// AVL: sibling subtree heights differ by at most one.
+    Height,
+    /// Sibling subtree sizes stay within a constant factor of each other,
+    /// which keeps average proofs shorter under skewed insertion patterns.
+    Weight,
+}
+
+impl BalancePolicy {
+    /// Whether `heavy` is too large next to its sibling `light`.
+    fn too_heavy(self, heavy: &NodeRef, light: &NodeRef) -> bool {
+        match self {
+            BalancePolicy::Height => height(heavy) > height(light) + 1,
+            BalancePolicy::Weight => weight(heavy) > WEIGHT_DELTA * weight(light),
+        }
+    }
+}
+
+/// Orders keys in place of byte-wise lexicographic order. It must be a total
+/// order that returns `Equal` only for identical keys; an ordering that
+/// ignores part of a key should break ties on the whole key.
+pub type KeyComparator = fn(&[u8], &[u8]) -> Ordering;
+
+/// Height of a subtree, counting an empty one as -1.
+fn height(node_ref: &NodeRef) -> i64 {
+    node_ref.as_ref().map_or(-1, |node| node.height as i64)
+}
+
+/// Size of a subtree plus one, the weight used by `BalancePolicy::Weight`.
+fn weight(node_ref: &NodeRef) -> usize {
+    node_ref.as_ref().map_or(0, |node| node.size) + 1
+}
+
+/// Smallest (`leftmost`) or largest key of a subtree.
+fn extreme_key(mut node_ref: &NodeRef, leftmost: bool) -> Option<&[u8]> {
+    let mut key = None;
+    while let Some(node) = node_ref {
+        key = Some(node.key.as_slice());
+        node_ref = if leftmost { &node.left } else { &node.right };
+    }
+    key
+}
+
+/// Balance parameters of weight-balanced trees (Hirai and Yamamoto's ⟨3, 2⟩).
+pub(crate) const WEIGHT_DELTA: usize = 3;
+pub(crate) const WEIGHT_GAMMA: usize = 2;
+
+#[derive(Debug, Clone)]
 pub struct Tree {
     pub root: NodeRef,
+    reserved_prefixes: Vec<Vec<u8>>,
+    max_height: Option<u32>,
+    max_size: Option<usize>,
+    balance: BalancePolicy,
+    /// `None` for byte-wise order.
+    compare: Option<KeyComparator>,
+    counters: Counters,
+}
+
+/// Trees are equal when they hold the same nodes under the same
+/// configuration; their operation counters are not compared.
+impl PartialEq for Tree {
+    fn eq(&self, other: &Self) -> bool {
+        self.root == other.root
+            && self.reserved_prefixes == other.reserved_prefixes
+            && self.max_height == other.max_height
+            && self.max_size == other.max_size
+            && self.balance == other.balance
+            && self.same_order(other)
+    }
 }
 
+impl Eq for Tree {}
+
 impl Tree {
     pub fn new() -> Self {
-        Tree { root: None }
+        Tree {
+            root: None,
+            reserved_prefixes: vec![],
+            max_height: None,
+            max_size: None,
+            balance: BalancePolicy::Height,
+            compare: None,
+            counters: Counters::default(),
+        }
+    }
+
+    /// Reject inserts of keys starting with any of `prefixes`, e.g. namespaces
+    /// used internally by the persistence layer.
+    pub fn with_reserved_prefixes(mut self, prefixes: Vec<Vec<u8>>) -> Self {
+        self.reserved_prefixes = prefixes;
+        self
+    }
+
+    /// Reject inserts that would grow the tree beyond `height`. The check
+    /// runs before rebalancing, so it may refuse an insert that a rotation
+    /// would have absorbed.
+    pub fn with_max_height(mut self, height: u32) -> Self {
+        self.max_height = Some(height);
+        self
+    }
+
+    /// Reject inserts of new keys once the tree holds `size` entries.
+    pub fn with_max_size(mut self, size: usize) -> Self {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// Rebalance with `policy` instead of height balancing. Must be set
+    /// before the first insert; node hashing is the same under either policy.
+    pub fn with_balance_policy(mut self, policy: BalancePolicy) -> Self {
+        self.balance = policy;
+        self
+    }
+
+    /// Order keys with `compare` instead of byte-wise. Must be set before the
+    /// first insert. It is not recorded by `serialize`, so load such a tree
+    /// with `deserialize_ordered_by`. Prefix scans fall back to a full scan,
+    /// since keys sharing a prefix need not be adjacent, and flat exports are
+    /// always in byte order.
+    pub fn with_comparator(mut self, compare: KeyComparator) -> Self {
+        self.compare = Some(compare);
+        self
+    }
+
+    /// The custom key order, or `None` for byte-wise order.
+    pub fn comparator(&self) -> Option<KeyComparator> {
+        self.compare
+    }
+
+    /// Whether `other` orders keys with the very same comparator.
+    fn same_order(&self, other: &Tree) -> bool {
+        match (self.compare, other.compare) {
+            (None, None) => true,
+            (Some(a), Some(b)) => std::ptr::fn_addr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn key_cmp(&self) -> KeyComparator {
+        self.compare.unwrap_or(<[u8]>::cmp)
     }
 
     pub fn root_hash(&self) -> Option<&Hash> {
         Some(&self.root.as_ref()?.merkle_hash)
     }
 
+    /// Number of entries, read from the root's subtree size.
+    pub fn len(&self) -> usize {
+        self.root.as_ref().map_or(0, |root| root.size)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Remove every entry, keeping the tree's configuration. Nodes are freed
+    /// from an explicit stack instead of recursing through boxed children, so
+    /// teardown never depends on the depth of the tree.
+    pub fn clear(&mut self) {
+        let mut stack: Vec<Box<Node>> = self.root.take().into_iter().collect();
+        while let Some(mut node) = stack.pop() {
+            stack.extend(node.left.take());
+            stack.extend(node.right.take());
+        }
+    }
+
+    /// Operation counts since the tree was created or its stats were reset.
+    /// Point lookups (`get`, `contains_key`, `get_with_index` and
+    /// `get_by_index`) count as gets; iteration, scans and
+    /// `first_key_value`/`last_key_value` are not counted.
+    pub fn stats(&self) -> TreeStats {
+        self.counters.snapshot()
+    }
+
+    pub fn reset_stats(&self) {
+        self.counters.reset()
+    }
+
     pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        let value = self.get_untracked(key);
+        self.counters.record_get(value.is_some());
+        value
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub(crate) fn get_untracked(&self, key: &[u8]) -> Option<&[u8]> {
+        let compare = self.key_cmp();
         let mut node_ref = &self.root;
         while let Some(ref node) = node_ref {
             let node_key: &[u8] = node.key.as_ref();
-            match node_key.cmp(key) {
+            match compare(node_key, key) {
                 Ordering::Greater => node_ref = &node.left,
                 Ordering::Less => node_ref = &node.right,
                 Ordering::Equal => return Some(node.value.as_ref()),
@@ -32,12 +245,74 @@ impl Tree {
         None
     }
 
+    /// Entry with the smallest key.
+    pub fn first_key_value(&self) -> Option<(&[u8], &[u8])> {
+        let mut node = self.root.as_ref()?;
+        while let Some(left) = &node.left {
+            node = left;
+        }
+        Some((node.key.as_ref(), node.value.as_ref()))
+    }
+
+    /// Entry with the largest key.
+    pub fn last_key_value(&self) -> Option<(&[u8], &[u8])> {
+        let mut node = self.root.as_ref()?;
+        while let Some(right) = &node.right {
+            node = right;
+        }
+        Some((node.key.as_ref(), node.value.as_ref()))
+    }
+
+    /// Look up `key` together with its zero-based position in key order.
+    pub fn get_with_index(&self, key: &[u8]) -> Option<(usize, &[u8])> {
+        let mut index = 0;
+        let compare = self.key_cmp();
+        let mut node_ref = &self.root;
+        while let Some(ref node) = node_ref {
+            let node_key: &[u8] = node.key.as_ref();
+            match compare(node_key, key) {
+                Ordering::Greater => node_ref = &node.left,
+                Ordering::Less => {
+                    index += node.left_size() + 1;
+                    node_ref = &node.right;
+                }
+                Ordering::Equal => {
+                    self.counters.record_get(true);
+                    return Some((index + node.left_size(), node.value.as_ref()));
+                }
+            }
+        }
+        self.counters.record_get(false);
+        None
+    }
+
+    /// Entry at zero-based position `index` in key order.
+    pub fn get_by_index(&self, mut index: usize) -> Option<(&[u8], &[u8])> {
+        let mut node_ref = &self.root;
+        while let Some(ref node) = node_ref {
+            match index.cmp(&node.left_size()) {
+                Ordering::Less => node_ref = &node.left,
+                Ordering::Greater => {
+                    index -= node.left_size() + 1;
+                    node_ref = &node.right;
+                }
+                Ordering::Equal => {
+                    self.counters.record_get(true);
+                    return Some((node.key.as_ref(), node.value.as_ref()));
+                }
+            }
+        }
+        self.counters.record_get(false);
+        None
+    }
+
     #[cfg(test)]
     pub fn get_node_ref(&self, key: &[u8]) -> Option<&Box<Node>> {
+        let compare = self.key_cmp();
         let mut node_ref = &self.root;
         while let Some(ref node) = node_ref {
             let node_key: &[u8] = node.key.as_ref();
-            match node_key.cmp(key) {
+            match compare(node_key, key) {
                 Ordering::Greater => node_ref = &node.left,
                 Ordering::Less => node_ref = &node.right,
                 Ordering::Equal => return Some(node),
@@ -46,35 +321,754 @@ impl Tree {
         None
     }
 
-    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
-        let node_ref = &mut self.root;
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.validate_entry(key, value)?;
+        self.check_growth(key)?;
+        let hashed = nodes_hashed();
+        let old_value =
+            Self::insert_iterative(self.balance, self.key_cmp(), &mut self.root, key, value);
+        self.counters.record_set();
+        self.counters.record_nodes_hashed(nodes_hashed() - hashed);
+        Ok(old_value)
+    }
+
+    pub(crate) fn validate_entry(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        if key.is_empty() {
+            return Err(AvlTreeError::EmptyKey.into());
+        }
+        if key.len() > MAX_KEY_SIZE {
+            return Err(AvlTreeError::KeyTooLarge(key.len()).into());
+        }
+        if value.len() > MAX_VALUE_SIZE {
+            return Err(AvlTreeError::ValueTooLarge(value.len()).into());
+        }
+        if self.reserved_prefixes.iter().any(|p| key.starts_with(p)) {
+            return Err(AvlTreeError::ReservedKeyPrefix.into());
+        }
+        Ok(())
+    }
+
+    /// Check the tree an insert of a new `key` would leave, after
+    /// rebalancing, against the configured limits.
+    fn check_growth(&self, key: &[u8]) -> Result<()> {
+        if self.max_height.is_none() && self.max_size.is_none() {
+            return Ok(());
+        }
+        if self.get_untracked(key).is_some() {
+            return Ok(());
+        }
+        self.check_limits(
+            || shape::insert(self.balance, self.key_cmp(), Shape::of(&self.root), key),
+            || self.len() + 1,
+        )
+    }
+
+    /// Descend to `key` detaching each node from its parent, then reattach
+    /// them bottom-up, updating and rebalancing each on the way. The path is
+    /// kept on an explicit stack rather than the call stack.
+    fn insert_iterative(
+        policy: BalancePolicy,
+        compare: KeyComparator,
+        root: &mut NodeRef,
+        key: &[u8],
+        value: &[u8],
+    ) -> Option<Vec<u8>> {
+        let mut path: Vec<(Box<Node>, Ordering)> = vec![];
         let mut old_value = None;
-        Self::insert_recursive(node_ref, key, value, &mut old_value);
+        let mut node_ref = root.take();
+        let mut subtree = loop {
+            let mut node = match node_ref {
+                Some(node) => node,
+                None => break as_node_ref(key.to_vec(), value.to_vec()),
+            };
+            let ordering = compare(&node.key, key);
+            node_ref = match ordering {
+                Ordering::Greater => node.left.take(),
+                Ordering::Less => node.right.take(),
+                Ordering::Equal => {
+                    old_value = Some(node.update_value(value));
+                    node.update();
+                    let mut subtree = Some(node);
+                    Self::balance_node(policy, &mut subtree);
+                    break subtree;
+                }
+            };
+            path.push((node, ordering));
+        };
+        while let Some((mut node, ordering)) = path.pop() {
+            if ordering == Ordering::Greater {
+                node.left = subtree;
+            } else {
+                node.right = subtree;
+            }
+            node.update();
+            subtree = Some(node);
+            Self::balance_node(policy, &mut subtree);
+        }
+        *root = subtree;
         old_value
     }
 
-    fn insert_recursive(
+    /// Apply one write, returning the key's previous value.
+    pub fn apply(&mut self, op: &Op) -> Result<Option<Vec<u8>>> {
+        match op {
+            Op::Set(key, value) => self.insert(key, value),
+            Op::Delete(key) => Ok(self.remove(key)),
+        }
+    }
+
+    /// Remove `key`, returning its value if it was present.
+    pub fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let hashed = nodes_hashed();
+        let removed = Self::remove_recursive(self.balance, self.key_cmp(), &mut self.root, key);
+        self.counters.record_delete();
+        self.counters.record_nodes_hashed(nodes_hashed() - hashed);
+        removed
+    }
+
+    fn remove_recursive(
+        policy: BalancePolicy,
+        compare: KeyComparator,
         node_ref: &mut NodeRef,
         key: &[u8],
-        value: &[u8],
-        old_value: &mut Option<Vec<u8>>,
+    ) -> Option<Vec<u8>> {
+        let node = node_ref.as_mut()?;
+        let node_key: &[u8] = node.key.as_ref();
+        let removed = match compare(node_key, key) {
+            Ordering::Greater => Self::remove_recursive(policy, compare, &mut node.left, key)?,
+            Ordering::Less => Self::remove_recursive(policy, compare, &mut node.right, key)?,
+            Ordering::Equal => {
+                let mut node = node_ref.take()?;
+                *node_ref = match (node.left.take(), node.right.take()) {
+                    (None, None) => None,
+                    (Some(child), None) | (None, Some(child)) => Some(child),
+                    (left, mut right) => {
+                        let mut successor = Self::remove_min(policy, &mut right);
+                        successor.left = left;
+                        successor.right = right;
+                        Some(successor)
+                    }
+                };
+                if let Some(replacement) = node_ref {
+                    replacement.update();
+                    Self::balance_node(policy, node_ref);
+                }
+                return Some(node.value);
+            }
+        };
+        node.update();
+        Self::balance_node(policy, node_ref);
+        Some(removed)
+    }
+
+    /// Detach the smallest node of a non-empty subtree, rebalancing on the way up.
+    fn remove_min(policy: BalancePolicy, node_ref: &mut NodeRef) -> Box<Node> {
+        let node = node_ref.as_mut().expect("[AVL]: Empty node in remove min");
+        if node.left.is_some() {
+            let min = Self::remove_min(policy, &mut node.left);
+            node.update();
+            Self::balance_node(policy, node_ref);
+            min
+        } else {
+            let mut min = node_ref.take().expect("[AVL]: Empty node in remove min");
+            *node_ref = min.right.take();
+            min
+        }
+    }
+
+    /// Remove every key in `keys`, returning the prior values in the same
+    /// order (`None` for absent or repeated keys). Each touched node is
+    /// rebalanced and rehashed once rather than once per removed key.
+    pub fn remove_many(&mut self, keys: &[&[u8]]) -> Vec<Option<Vec<u8>>> {
+        let hashed = nodes_hashed();
+        let mut sorted = keys.to_vec();
+        let compare = self.key_cmp();
+        sorted.sort_unstable_by(|a, b| compare(a, b));
+        sorted.dedup();
+        let mut removed = HashMap::with_capacity(sorted.len());
+        self.root = Self::remove_many_recursive(
+            self.balance,
+            compare,
+            self.root.take(),
+            &sorted,
+            &mut removed,
+        );
+        for _ in keys {
+            self.counters.record_delete();
+        }
+        self.counters.record_nodes_hashed(nodes_hashed() - hashed);
+        keys.iter().map(|key| removed.remove(key)).collect()
+    }
+
+    /// `keys` must be sorted and free of duplicates.
+    fn remove_many_recursive<'k>(
+        policy: BalancePolicy,
+        compare: KeyComparator,
+        node_ref: NodeRef,
+        keys: &[&'k [u8]],
+        removed: &mut HashMap<&'k [u8], Vec<u8>>,
+    ) -> NodeRef {
+        let mut node = match node_ref {
+            Some(node) if !keys.is_empty() => node,
+            node_ref => return node_ref,
+        };
+        let split = keys.partition_point(|key| compare(key, &node.key) == Ordering::Less);
+        let found = keys.get(split).filter(|key| **key == node.key.as_slice());
+        let right_keys = &keys[split + found.is_some() as usize..];
+        let left =
+            Self::remove_many_recursive(policy, compare, node.left.take(), &keys[..split], removed);
+        let right =
+            Self::remove_many_recursive(policy, compare, node.right.take(), right_keys, removed);
+        match found {
+            Some(key) => {
+                removed.insert(key, std::mem::take(&mut node.value));
+                Self::concat(policy, left, right)
+            }
+            None => Self::join(policy, left, node, right),
+        }
+    }
+
+    /// Remove every entry for which `f(key, value)` is false, calling `f` in
+    /// ascending key order. Only nodes above a removed entry are rebalanced
+    /// and rehashed, each once.
+    pub fn retain<F: FnMut(&[u8], &[u8]) -> bool>(&mut self, mut f: F) {
+        let hashed = nodes_hashed();
+        let mut removed = 0;
+        let (root, _) =
+            Self::retain_recursive(self.balance, self.root.take(), &mut f, &mut removed);
+        self.root = root;
+        for _ in 0..removed {
+            self.counters.record_delete();
+        }
+        self.counters.record_nodes_hashed(nodes_hashed() - hashed);
+    }
+
+    /// Returns the filtered subtree and whether anything below was removed.
+    fn retain_recursive<F: FnMut(&[u8], &[u8]) -> bool>(
+        policy: BalancePolicy,
+        node_ref: NodeRef,
+        f: &mut F,
+        removed: &mut usize,
+    ) -> (NodeRef, bool) {
+        let mut node = match node_ref {
+            Some(node) => node,
+            None => return (None, false),
+        };
+        let (left, left_changed) = Self::retain_recursive(policy, node.left.take(), f, removed);
+        let keep = f(&node.key, &node.value);
+        let (right, right_changed) = Self::retain_recursive(policy, node.right.take(), f, removed);
+        if !keep {
+            *removed += 1;
+            return (Self::concat(policy, left, right), true);
+        }
+        if !left_changed && !right_changed {
+            node.left = left;
+            node.right = right;
+            return (Some(node), false);
+        }
+        (Self::join(policy, left, node, right), true)
+    }
+
+    /// Apply `ops` in order as one atomic write: if any set is rejected by
+    /// the checks of `insert`, or the final tree would exceed the configured
+    /// size or height limit, the tree is left unchanged. Returns each op's
+    /// previous value, as if the ops had been applied one by one. Only the
+    /// last op on each key touches the tree, and each touched node is
+    /// rebalanced and rehashed once.
+    pub fn apply_batch(&mut self, ops: &[Op]) -> Result<Vec<Option<Vec<u8>>>> {
+        for op in ops {
+            if let Op::Set(key, value) = op {
+                self.validate_entry(key, value)?;
+            }
+        }
+        let hashed = nodes_hashed();
+        let mut order: Vec<usize> = (0..ops.len()).collect();
+        let compare = self.key_cmp();
+        order.sort_by(|a, b| compare(ops[*a].key(), ops[*b].key()));
+        let mut writes: Vec<(&[u8], Option<&[u8]>)> = vec![];
+        for &i in &order {
+            let write = match &ops[i] {
+                Op::Set(key, value) => (key.as_slice(), Some(value.as_slice())),
+                Op::Delete(key) => (key.as_slice(), None),
+            };
+            match writes.last_mut() {
+                Some(last) if last.0 == write.0 => *last = write,
+                _ => writes.push(write),
+            }
+        }
+
+        self.check_limits(
+            || shape::batch(self.balance, compare, Shape::of(&self.root), &writes),
+            || {
+                let mut size = self.len();
+                for (key, value) in &writes {
+                    match (self.get_untracked(key).is_some(), value) {
+                        (false, Some(_)) => size += 1,
+                        (true, None) => size -= 1,
+                        _ => {}
+                    }
+                }
+                size
+            },
+        )?;
+        let mut old = HashMap::with_capacity(writes.len());
+        let root = self.root.take();
+        self.root = Self::apply_batch_recursive(self.balance, compare, root, &writes, &mut old);
+
+        let mut previous = vec![None; ops.len()];
+        let mut current = None;
+        for (n, &i) in order.iter().enumerate() {
+            let key = ops[i].key();
+            if n == 0 || key != ops[order[n - 1]].key() {
+                current = old.get(key).cloned();
+            }
+            previous[i] = match &ops[i] {
+                Op::Set(_, value) => {
+                    self.counters.record_set();
+                    current.replace(value.clone())
+                }
+                Op::Delete(_) => {
+                    self.counters.record_delete();
+                    current.take()
+                }
+            };
+        }
+        self.counters.record_nodes_hashed(nodes_hashed() - hashed);
+        Ok(previous)
+    }
+
+    /// Check the height and size a bulk write would leave against the
+    /// configured limits, before the write touches the tree. `shape` dry-runs
+    /// the write and `size` counts the entries it leaves; each is only called
+    /// when its limit is set.
+    fn check_limits<'a, S, N>(&self, shape: S, size: N) -> Result<()>
+    where
+        S: FnOnce() -> Shape<'a>,
+        N: FnOnce() -> usize,
+    {
+        if let Some(max_height) = self.max_height {
+            if shape().height() > max_height as i64 {
+                return Err(AvlTreeError::HeightLimitExceeded(max_height).into());
+            }
+        }
+        if let Some(max_size) = self.max_size {
+            if size() > max_size {
+                return Err(AvlTreeError::SizeLimitExceeded(max_size).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// `writes` must be sorted by key and free of duplicate keys; `None`
+    /// deletes the key.
+    fn apply_batch_recursive<'k>(
+        policy: BalancePolicy,
+        compare: KeyComparator,
+        node_ref: NodeRef,
+        writes: &[(&'k [u8], Option<&[u8]>)],
+        old: &mut HashMap<&'k [u8], Vec<u8>>,
+    ) -> NodeRef {
+        let mut node = match node_ref {
+            Some(node) if !writes.is_empty() => node,
+            None => {
+                let entries: Vec<_> = writes
+                    .iter()
+                    .filter_map(|&(key, value)| Some((key.to_vec(), value?.to_vec())))
+                    .collect();
+                let len = entries.len();
+                return Self::build_balanced(&mut entries.into_iter(), len);
+            }
+            node_ref => return node_ref,
+        };
+        let split = writes.partition_point(|(key, _)| compare(key, &node.key) == Ordering::Less);
+        let found = writes
+            .get(split)
+            .filter(|(key, _)| *key == node.key.as_slice());
+        let right_writes = &writes[split + found.is_some() as usize..];
+        let left_writes = &writes[..split];
+        let left = Self::apply_batch_recursive(policy, compare, node.left.take(), left_writes, old);
+        let right =
+            Self::apply_batch_recursive(policy, compare, node.right.take(), right_writes, old);
+        match found {
+            Some((key, Some(value))) => {
+                old.insert(key, node.update_value(value));
+                Self::join(policy, left, node, right)
+            }
+            Some((key, None)) => {
+                old.insert(key, std::mem::take(&mut node.value));
+                Self::concat(policy, left, right)
+            }
+            None => Self::join(policy, left, node, right),
+        }
+    }
+
+    /// Move the entries with keys at or after `key` into a new tree with the
+    /// same configuration, leaving the smaller ones in place. Both halves are
+    /// rebuilt with joins along the split path only.
+    pub fn split_off(&mut self, key: &[u8]) -> Tree {
+        let hashed = nodes_hashed();
+        let (left, mid, right) = Self::split(self.balance, self.key_cmp(), self.root.take(), key);
+        self.root = left;
+        let mut other = Tree {
+            root: None,
+            reserved_prefixes: self.reserved_prefixes.clone(),
+            max_height: self.max_height,
+            max_size: self.max_size,
+            balance: self.balance,
+            compare: self.compare,
+            counters: Counters::default(),
+        };
+        other.root = match mid {
+            Some(mid) => Self::join(self.balance, None, mid, right),
+            None => right,
+        };
+        self.counters.record_nodes_hashed(nodes_hashed() - hashed);
+        other
+    }
+
+    /// Move every entry of `other` into this tree, replacing the values of
+    /// keys present in both. When all of `other`'s keys are greater, the two
+    /// trees are joined along one spine; otherwise they are merged by
+    /// splitting, which costs O(m log(n / m + 1)) for the smaller size `m`.
+    /// Fails, leaving this tree unchanged, if an entry is rejected by the
+    /// checks of `insert` or the result would exceed a configured limit.
+    pub fn append(&mut self, mut other: Tree) -> Result<()> {
+        let mut entries = 0;
+        for (key, value) in other.iter() {
+            self.validate_entry(key, value)?;
+            entries += 1;
+        }
+        let hashed = nodes_hashed();
+        let compare = self.key_cmp();
+        if other.balance != self.balance || !self.same_order(&other) {
+            // Joins assume both sides follow this tree's policy and order.
+            let mut entries: Vec<_> = std::mem::replace(&mut other, Tree::new())
+                .into_iter()
+                .collect();
+            entries.sort_by(|a, b| compare(&a.0, &b.0));
+            let len = entries.len();
+            other.root = Self::build_balanced(&mut entries.into_iter(), len);
+        }
+        let disjoint = match (self.last_key_value(), other.first_key_value()) {
+            (Some((last, _)), Some((first, _))) => compare(last, first) == Ordering::Less,
+            _ => true,
+        };
+        self.check_limits(
+            || {
+                let (root, other_root) = (Shape::of(&self.root), Shape::of(&other.root));
+                if disjoint {
+                    shape::concat(self.balance, root, other_root)
+                } else {
+                    shape::union(self.balance, compare, root, other_root)
+                }
+            },
+            || {
+                let new_keys = other
+                    .iter()
+                    .filter(|(key, _)| self.get_untracked(key).is_none());
+                self.len() + new_keys.count()
+            },
+        )?;
+        let (root, other_root) = (self.root.take(), other.root.take());
+        self.root = if disjoint {
+            Self::concat(self.balance, root, other_root)
+        } else {
+            Self::union(self.balance, compare, root, other_root)
+        };
+        for _ in 0..entries {
+            self.counters.record_set();
+        }
+        self.counters.record_nodes_hashed(nodes_hashed() - hashed);
+        Ok(())
+    }
+
+    /// Merge two subtrees, keeping `right`'s node for keys in both.
+    fn union(
+        policy: BalancePolicy,
+        compare: KeyComparator,
+        left: NodeRef,
+        right: NodeRef,
+    ) -> NodeRef {
+        let mut node = match (left.is_some(), right) {
+            (_, None) => return left,
+            (false, right) => return right,
+            (true, Some(node)) => node,
+        };
+        let (less, _, greater) = Self::split(policy, compare, left, &node.key);
+        let left = Self::union(policy, compare, less, node.left.take());
+        let right = Self::union(policy, compare, greater, node.right.take());
+        Self::join(policy, left, node, right)
+    }
+
+    /// Split a subtree into the parts before and after `key`, and the
+    /// detached node holding `key` if there is one.
+    fn split(
+        policy: BalancePolicy,
+        compare: KeyComparator,
+        node_ref: NodeRef,
+        key: &[u8],
+    ) -> (NodeRef, NodeRef, NodeRef) {
+        let mut node = match node_ref {
+            Some(node) => node,
+            None => return (None, None, None),
+        };
+        let left = node.left.take();
+        let right = node.right.take();
+        match compare(&node.key, key) {
+            Ordering::Equal => (left, Some(node), right),
+            Ordering::Greater => {
+                let (less, mid, greater) = Self::split(policy, compare, left, key);
+                (less, mid, Self::join(policy, greater, node, right))
+            }
+            Ordering::Less => {
+                let (less, mid, greater) = Self::split(policy, compare, right, key);
+                (Self::join(policy, left, node, less), mid, greater)
+            }
+        }
+    }
+
+    /// Build a balanced tree from `left`, `mid` and `right`, where every key
+    /// of `left` is smaller than `mid` and every key of `right` is larger.
+    /// The subtrees may differ in height or size by any amount.
+    fn join(policy: BalancePolicy, left: NodeRef, mut mid: Box<Node>, right: NodeRef) -> NodeRef {
+        if policy.too_heavy(&left, &right) {
+            let mut left = left.expect("[AVL]: Empty left subtree in join");
+            left.right = Self::join(policy, left.right.take(), mid, right);
+            left.update();
+            let mut node_ref = Some(left);
+            Self::balance_node(policy, &mut node_ref);
+            node_ref
+        } else if policy.too_heavy(&right, &left) {
+            let mut right = right.expect("[AVL]: Empty right subtree in join");
+            right.left = Self::join(policy, left, mid, right.left.take());
+            right.update();
+            let mut node_ref = Some(right);
+            Self::balance_node(policy, &mut node_ref);
+            node_ref
+        } else {
+            mid.left = left;
+            mid.right = right;
+            mid.update();
+            Some(mid)
+        }
+    }
+
+    /// Like `join` without a middle node.
+    fn concat(policy: BalancePolicy, left: NodeRef, mut right: NodeRef) -> NodeRef {
+        if right.is_none() {
+            return left;
+        }
+        let min = Self::remove_min(policy, &mut right);
+        Self::join(policy, left, min, right)
+    }
+
+    /// A tree of the same shape whose values are `f(key, value)`, with every
+    /// hash rebuilt bottom-up in one pass. Fails if a new value is rejected by
+    /// the same checks as `insert`.
+    pub fn map_values<F: FnMut(&[u8], &[u8]) -> Vec<u8>>(&self, mut f: F) -> Result<Tree> {
+        let root = self.map_values_recursive(&self.root, &mut f)?;
+        Ok(Tree {
+            root,
+            reserved_prefixes: self.reserved_prefixes.clone(),
+            max_height: self.max_height,
+            max_size: self.max_size,
+            balance: self.balance,
+            compare: self.compare,
+            counters: Counters::default(),
+        })
+    }
+
+    fn map_values_recursive<F: FnMut(&[u8], &[u8]) -> Vec<u8>>(
+        &self,
+        node_ref: &NodeRef,
+        f: &mut F,
+    ) -> Result<NodeRef> {
+        let node = match node_ref {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        let left = self.map_values_recursive(&node.left, f)?;
+        let value = f(&node.key, &node.value);
+        self.validate_entry(&node.key, &value)?;
+        let right = self.map_values_recursive(&node.right, f)?;
+        let mut mapped = Box::new(Node {
+            key: node.key.clone(),
+            hash: leaf_hash(&node.key, &value),
+            value,
+            merkle_hash: vec![],
+            height: 0,
+            size: 0,
+            left,
+            right,
+        });
+        mapped.update();
+        Ok(Some(mapped))
+    }
+
+    /// Build a tree from entries in strictly ascending key order in one
+    /// bottom-up pass, hashing each node once. Each subtree is rooted at its
+    /// middle entry, so the result is perfectly balanced.
+    pub fn from_sorted_iter<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(iter: I) -> Result<Tree> {
+        let mut tree = Tree::new();
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+        for (key, value) in iter {
+            tree.validate_entry(&key, &value)?;
+            if matches!(entries.last(), Some((last, _)) if *last >= key) {
+                return Err(AvlTreeError::UnsortedKeys.into());
+            }
+            entries.push((key, value));
+        }
+        let len = entries.len();
+        tree.root = Self::build_balanced(&mut entries.into_iter(), len);
+        Ok(tree)
+    }
+
+    /// Build a height-balanced subtree from the next `len` entries of
+    /// `entries`, which must be in strictly ascending key order.
+    pub(crate) fn build_balanced<I: Iterator<Item = (Vec<u8>, Vec<u8>)>>(
+        entries: &mut I,
+        len: usize,
+    ) -> NodeRef {
+        if len == 0 {
+            return None;
+        }
+        let left = Self::build_balanced(entries, len / 2);
+        let (key, value) = entries.next()?;
+        let mut node = as_node_ref(key, value)?;
+        node.left = left;
+        node.right = Self::build_balanced(entries, len - len / 2 - 1);
+        if !node.is_leaf() {
+            node.update();
+        }
+        Some(node)
+    }
+
+    /// Visit entries whose key starts with `prefix` in ascending order,
+    /// skipping subtrees that cannot contain such keys.
+    pub(crate) fn for_each_with_prefix<F: FnMut(&[u8], &[u8])>(&self, prefix: &[u8], mut f: F) {
+        if self.compare.is_none() {
+            return Self::for_each_with_prefix_recursive(&self.root, prefix, &mut f);
+        }
+        for (key, value) in self.iter() {
+            if key.starts_with(prefix) {
+                f(key, value);
+            }
+        }
+    }
+
+    fn for_each_with_prefix_recursive<F: FnMut(&[u8], &[u8])>(
+        node_ref: &NodeRef,
+        prefix: &[u8],
+        f: &mut F,
     ) {
         if let Some(node) = node_ref {
             let node_key: &[u8] = node.key.as_ref();
-            match node_key.cmp(key) {
-                Ordering::Greater => Self::insert_recursive(&mut node.left, key, value, old_value),
-                Ordering::Less => Self::insert_recursive(&mut node.right, key, value, old_value),
-                Ordering::Equal => return *old_value = Some(node.update_value(value)),
+            let matches = node_key.starts_with(prefix);
+            if node_key >= prefix {
+                Self::for_each_with_prefix_recursive(&node.left, prefix, f);
             }
-            node.update();
-            Self::balance_node(node_ref);
+            if matches {
+                f(node_key, node.value.as_ref());
+            }
+            if matches || node_key < prefix {
+                Self::for_each_with_prefix_recursive(&node.right, prefix, f);
+            }
+        }
+    }
+
+    /// Bytes of keys and values stored under `prefix`, to attribute the size
+    /// of a tree to the namespaces it holds. This is the in-memory payload,
+    /// not disk usage: node hashes and storage overhead are not counted.
+    pub fn usage_by_prefix(&self, prefix: &[u8]) -> usize {
+        let mut usage = 0;
+        self.for_each_with_prefix(prefix, |key, value| usage += key.len() + value.len());
+        usage
+    }
+
+    /// Return up to `limit` entries following `cursor` (from the start when
+    /// `None`), in ascending order or descending when `reverse` is set. The
+    /// returned cursor is the last key of the page. A `limit` of zero is
+    /// rejected, as its empty page could not carry a cursor.
+    pub fn paginate(&self, cursor: Option<&[u8]>, limit: usize, reverse: bool) -> Result<Page> {
+        if limit == 0 {
+            return Err(AvlTreeError::ZeroPageLimit.into());
+        }
+        let (lower, upper) = match (cursor, reverse) {
+            (None, _) => (Bound::Unbounded, Bound::Unbounded),
+            (Some(c), false) => (Bound::Excluded(c), Bound::Unbounded),
+            (Some(c), true) => (Bound::Unbounded, Bound::Excluded(c)),
+        };
+        let mut entries = vec![];
+        let mut has_more = false;
+        let compare = self.key_cmp();
+        Self::visit_range(&self.root, compare, lower, upper, reverse, &mut |node| {
+            if entries.len() == limit {
+                has_more = true;
+                return false;
+            }
+            entries.push((node.key.clone(), node.value.clone()));
+            true
+        });
+        let next_cursor = if has_more {
+            entries.last().map(|(key, _)| key.clone())
+        } else {
+            None
+        };
+        Ok(Page {
+            entries,
+            next_cursor,
+        })
+    }
+
+    /// In-order walk over nodes with keys between `lower` and `upper`,
+    /// descending when `reverse` is set, until `f` returns false. Returns
+    /// false if the walk was stopped early.
+    fn visit_range<'a, F: FnMut(&'a Node) -> bool>(
+        node_ref: &'a NodeRef,
+        compare: KeyComparator,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        reverse: bool,
+        f: &mut F,
+    ) -> bool {
+        let node = match node_ref {
+            Some(node) => node,
+            None => return true,
+        };
+        let node_key: &[u8] = node.key.as_ref();
+        let above_lower = match lower {
+            Bound::Included(l) => compare(node_key, l) != Ordering::Less,
+            Bound::Excluded(l) => compare(node_key, l) == Ordering::Greater,
+            Bound::Unbounded => true,
+        };
+        let below_upper = match upper {
+            Bound::Included(u) => compare(node_key, u) != Ordering::Greater,
+            Bound::Excluded(u) => compare(node_key, u) == Ordering::Less,
+            Bound::Unbounded => true,
+        };
+        let (first, first_wanted, second, second_wanted) = if reverse {
+            (&node.right, below_upper, &node.left, above_lower)
         } else {
-            *node_ref = as_node_ref(key.to_vec(), value.to_vec());
+            (&node.left, above_lower, &node.right, below_upper)
+        };
+        if first_wanted && !Self::visit_range(first, compare, lower, upper, reverse, f) {
+            return false;
+        }
+        if above_lower && below_upper && !f(node) {
+            return false;
         }
+        !second_wanted || Self::visit_range(second, compare, lower, upper, reverse, f)
     }
 
-    /// Rebalance the AVL tree by performing rotations, if needed.
-    fn balance_node(node_ref: &mut NodeRef) {
+    /// Rebalance the subtree by performing rotations, if needed.
+    fn balance_node(policy: BalancePolicy, node_ref: &mut NodeRef) {
+        match policy {
+            BalancePolicy::Height => Self::balance_height(node_ref),
+            BalancePolicy::Weight => Self::balance_weight(node_ref),
+        }
+    }
+
+    fn balance_height(node_ref: &mut NodeRef) {
         let node = node_ref
             .as_mut()
             .expect("[AVL]: Empty node in node balance");
@@ -84,7 +1078,7 @@ impl Tree {
                 .left
                 .as_mut()
                 .expect("[AVL]: Unexpected empty left node");
-            if left.balance_factor() < 1 {
+            if left.balance_factor() < 0 {
                 Tree::rotate_left(&mut node.left);
             }
             Tree::rotate_right(node_ref);
@@ -93,7 +1087,32 @@ impl Tree {
                 .right
                 .as_mut()
                 .expect("[AVL]: Unexpected empty right node");
-            if right.balance_factor() > -1 {
+            if right.balance_factor() > 0 {
+                Tree::rotate_right(&mut node.right);
+            }
+            Tree::rotate_left(node_ref);
+        }
+    }
+
+    fn balance_weight(node_ref: &mut NodeRef) {
+        let node = node_ref
+            .as_mut()
+            .expect("[AVL]: Empty node in node balance");
+        if BalancePolicy::Weight.too_heavy(&node.left, &node.right) {
+            let left = node
+                .left
+                .as_mut()
+                .expect("[AVL]: Unexpected empty left node");
+            if weight(&left.right) >= WEIGHT_GAMMA * weight(&left.left) {
+                Tree::rotate_left(&mut node.left);
+            }
+            Tree::rotate_right(node_ref);
+        } else if BalancePolicy::Weight.too_heavy(&node.right, &node.left) {
+            let right = node
+                .right
+                .as_mut()
+                .expect("[AVL]: Unexpected empty right node");
+            if weight(&right.left) >= WEIGHT_GAMMA * weight(&right.right) {
                 Tree::rotate_right(&mut node.right);
             }
             Tree::rotate_left(node_ref);
@@ -147,10 +1166,14 @@ impl Tree {
             return false;
         }
 
-        if node.balance_factor() >= 2 {
+        if node.size != 1 + node.left_size() + node.right_size() {
             return false;
         }
-        if let Some(left) = &node.left {
+
+        if node.balance_factor().abs() >= 2 {
+            return false;
+        }
+        if let Some(left) = &node.left {
             if !Self::validate_recursive(left) {
                 return false;
             }
@@ -164,65 +1187,394 @@ impl Tree {
     }
 
     pub fn get_proof(&self, key: &[u8]) -> Option<Proof> {
-        self.get_proof_recursive(key, &self.root)
+        let proof = self.build_proof(key);
+        if proof.is_some() {
+            self.counters.record_proof();
+        }
+        proof
     }
 
-    fn get_proof_recursive(&self, key: &[u8], node: &NodeRef) -> Option<Proof> {
-        if let Some(node) = node {
-            let empty_hash = [];
+    /// Entries with keys between `start` and `end` in ascending order, each
+    /// with its existence proof against the current root. Path nodes above
+    /// a subtree are computed once and shared by every entry below it.
+    pub fn iter_with_proofs<'a>(
+        &'a self,
+        start: Bound<&'a [u8]>,
+        end: Bound<&'a [u8]>,
+    ) -> ProofIter<'a> {
+        let mut iter = ProofIter {
+            compare: self.key_cmp(),
+            counters: &self.counters,
+            end,
+            stack: vec![],
+            path: vec![],
+        };
+        iter.descend(&self.root, 0, start);
+        iter
+    }
+
+    /// Collect the path nodes on the way down to `key`, root first, then
+    /// reverse them into the leaf-to-root order of `Proof::path`.
+    fn build_proof(&self, key: &[u8]) -> Option<Proof> {
+        let depth = self
+            .root
+            .as_ref()
+            .map_or(0, |root| root.height as usize + 1);
+        let mut path = Vec::with_capacity(depth);
+        let compare = self.key_cmp();
+        let mut node_ref = &self.root;
+        while let Some(node) = node_ref {
             let node_key: &[u8] = node.key.as_ref();
-            let (mut proof, prefix, suffix) = match node_key.cmp(key) {
+            match compare(node_key, key) {
                 Ordering::Greater => {
-                    let proof = self.get_proof_recursive(key, &node.left)?;
-                    let prefix = vec![];
-                    let mut suffix: Vec<u8> = Vec::with_capacity(64);
+                    let mut suffix = Vec::with_capacity(64);
                     suffix.extend(node.hash.iter());
-                    suffix.extend(node.right_hash().unwrap_or(&empty_hash));
-                    (proof, prefix, suffix)
+                    suffix.extend(node.right_hash().unwrap_or(&[]));
+                    path.push(ProofPathNode {
+                        prefix: vec![],
+                        suffix,
+                    });
+                    node_ref = &node.left;
                 }
                 Ordering::Less => {
-                    let proof = self.get_proof_recursive(key, &node.right)?;
-                    let suffix = vec![];
-                    let mut prefix: Vec<u8> = Vec::with_capacity(64);
-                    prefix.extend(node.left_hash().unwrap_or(&empty_hash));
+                    let mut prefix = Vec::with_capacity(64);
+                    prefix.extend(node.left_hash().unwrap_or(&[]));
                     prefix.extend(node.hash.iter());
-                    (proof, prefix, suffix)
+                    path.push(ProofPathNode {
+                        prefix,
+                        suffix: vec![],
+                    });
+                    node_ref = &node.right;
                 }
                 Ordering::Equal => {
-                    let proof = Proof {
+                    path.push(ProofPathNode {
+                        prefix: node.left_hash().unwrap_or(&[]).to_vec(),
+                        suffix: node.right_hash().unwrap_or(&[]).to_vec(),
+                    });
+                    path.reverse();
+                    return Some(Proof {
                         key: node.key.clone(),
                         value: node.value.clone(),
-                        path: vec![],
-                    };
-                    let prefix = node.left_hash().unwrap_or(&empty_hash).to_vec();
-                    let suffix = node.right_hash().unwrap_or(&empty_hash).to_vec();
-                    (proof, prefix, suffix)
+                        path,
+                    });
                 }
-            };
-
-            let path_node = ProofPathNode { prefix, suffix };
-            proof.path.push(path_node);
-            Some(proof)
-        } else {
-            None
+            }
         }
+        None
     }
 
     pub fn verify_existence(&self, key: &[u8], value: &[u8], proof: &Proof) -> Result<()> {
-        assert!(proof.key.eq(key));
-        assert!(proof.value.eq(value));
+        if proof.key != key || proof.value != value {
+            return Err(AvlTreeError::ValueNonExistence.into());
+        }
         let root = self.root_hash().ok_or(AvlTreeError::RootHashNotFound)?;
-        if proof.calc_root_hash().eq(root) {
-            Ok(())
-        } else {
-            Err(AvlTreeError::ValueNonExistence.into())
+        proof.verify_root(root)
+    }
+
+    /// Write the tree as a flat pre-order sequence of nodes, including heights
+    /// and hashes, so `deserialize` can rebuild it without rehashing.
+    /// Height-balanced trees are written without a header; weight-balanced
+    /// ones are prefixed with `LAYOUT_WEIGHT_BALANCED`.
+    pub fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+        if self.balance == BalancePolicy::Weight {
+            writer.write_all(&[LAYOUT_WEIGHT_BALANCED])?;
+        }
+        Self::serialize_recursive(&self.root, writer)
+    }
+
+    fn serialize_recursive<W: Write>(node_ref: &NodeRef, writer: &mut W) -> Result<()> {
+        let node = match node_ref {
+            Some(node) => node,
+            None => return Ok(writer.write_all(&[NODE_ABSENT])?),
+        };
+        writer.write_all(&[NODE_PRESENT])?;
+        writer.write_all(&node.height.to_be_bytes())?;
+        for bytes in [&node.key, &node.value, &node.hash, &node.merkle_hash] {
+            write_bytes(writer, bytes)?;
+        }
+        Self::serialize_recursive(&node.left, writer)?;
+        Self::serialize_recursive(&node.right, writer)
+    }
+
+    /// Rebuild a tree written by `serialize`, checking each node as it is
+    /// read: its hashes, height and size against its contents, and its key
+    /// order and balance against its children. Fails with `NodeHashMismatch`
+    /// or `InvariantViolation` on the first node that disagrees, so corrupt
+    /// input never loads as a tree that lookups can not search.
+    pub fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        Self::deserialize_with(reader, None, true)
+    }
+
+    /// Like `deserialize`, for a tree written while ordered by `compare`,
+    /// which the loaded tree keeps using.
+    pub fn deserialize_ordered_by<R: Read>(reader: &mut R, compare: KeyComparator) -> Result<Self> {
+        Self::deserialize_with(reader, Some(compare), true)
+    }
+
+    /// Rebuild a tree written by `serialize`, trusting stored hashes, heights
+    /// and key order. Only for input that can not have been corrupted or
+    /// tampered with since it was written.
+    pub fn deserialize_unverified<R: Read>(reader: &mut R) -> Result<Self> {
+        Self::deserialize_with(reader, None, false)
+    }
+
+    fn deserialize_with<R: Read>(
+        reader: &mut R,
+        compare: Option<KeyComparator>,
+        verify: bool,
+    ) -> Result<Self> {
+        let mut tree = Tree::new();
+        tree.compare = compare;
+        let mut marker = read_array::<R, 1>(reader)?[0];
+        if marker == LAYOUT_WEIGHT_BALANCED {
+            tree.balance = BalancePolicy::Weight;
+            marker = read_array::<R, 1>(reader)?[0];
+        }
+        let mut pending = vec![];
+        let root = tree.deserialize_nodes(marker, reader, verify, &mut pending);
+        // Free whatever was read before a failure without recursing into it.
+        for (node, _) in pending {
+            tree.root = Some(Box::new(node));
+            tree.clear();
+        }
+        tree.root = root?;
+        Ok(tree)
+    }
+
+    /// Read the pre-order node sequence starting at `marker`. Nodes whose
+    /// subtrees are still being read wait in `pending`, innermost last and
+    /// flagged once their left child is attached, so nesting in the input
+    /// never deepens the call stack. On error, `pending` holds every node
+    /// read so far.
+    fn deserialize_nodes<R: Read>(
+        &self,
+        mut marker: u8,
+        reader: &mut R,
+        verify: bool,
+        pending: &mut Vec<(Node, bool)>,
+    ) -> Result<NodeRef> {
+        loop {
+            let mut done = match marker {
+                NODE_ABSENT => None,
+                NODE_PRESENT => {
+                    pending.push((Self::read_node(reader)?, false));
+                    marker = read_array::<R, 1>(reader)?[0];
+                    continue;
+                }
+                _ => return Err(AvlTreeError::MalformedEncoding.into()),
+            };
+            loop {
+                match pending.last_mut() {
+                    None => return Ok(done),
+                    Some((node, has_left @ false)) => {
+                        node.left = done;
+                        *has_left = true;
+                        break;
+                    }
+                    Some(_) => {
+                        let (mut node, _) = pending.pop().expect("[AVL]: Pending node vanished");
+                        node.right = done;
+                        if let Err(err) = self.finish_node(&mut node, verify) {
+                            pending.push((node, true));
+                            return Err(err);
+                        }
+                        done = Some(Box::new(node));
+                    }
+                }
+            }
+            marker = read_array::<R, 1>(reader)?[0];
         }
     }
+
+    /// Read a node's own fields, leaving its children to the caller.
+    fn read_node<R: Read>(reader: &mut R) -> Result<Node> {
+        let height = u32::from_be_bytes(read_array(reader)?);
+        let key = read_bytes(reader)?;
+        let value = read_bytes(reader)?;
+        let hash = read_bytes(reader)?;
+        let merkle_hash = read_bytes(reader)?;
+        Ok(Node {
+            key,
+            value,
+            hash,
+            merkle_hash,
+            height,
+            size: 1,
+            left: None,
+            right: None,
+        })
+    }
+
+    /// Derive a node's size once both children are attached and, if
+    /// `verify`, check its stored hashes and height against its contents and
+    /// its key order and balance against its already checked children.
+    fn finish_node(&self, node: &mut Node, verify: bool) -> Result<()> {
+        node.size = 1 + node.left_size() + node.right_size();
+        if !verify {
+            return Ok(());
+        }
+        let stored = (node.merkle_hash.clone(), node.height);
+        let hash_ok = node.hash == leaf_hash(&node.key, &node.value);
+        node.update();
+        if !hash_ok || stored != (node.merkle_hash.clone(), node.height) {
+            return Err(AvlTreeError::NodeHashMismatch(node.key.clone()).into());
+        }
+        let compare = self.key_cmp();
+        let in_order = extreme_key(&node.left, false)
+            .is_none_or(|max| compare(max, &node.key) == Ordering::Less)
+            && extreme_key(&node.right, true)
+                .is_none_or(|min| compare(&node.key, min) == Ordering::Less);
+        let balanced = match self.balance {
+            BalancePolicy::Height => node.balance_factor().abs() < 2,
+            BalancePolicy::Weight => {
+                !self.balance.too_heavy(&node.left, &node.right)
+                    && !self.balance.too_heavy(&node.right, &node.left)
+            }
+        };
+        if !in_order || !balanced {
+            let problem = if in_order {
+                "unbalanced"
+            } else {
+                "out of order"
+            };
+            return Err(AvlTreeError::InvariantViolation(format!(
+                "node {} is {}",
+                hex::encode(&node.key),
+                problem
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Tree {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// Inserts each entry in turn, later duplicates overwriting earlier ones.
+///
+/// # Panics
+///
+/// If an entry is rejected by `insert`; use `insert` or `apply_batch` to
+/// handle such errors.
+impl Extend<(Vec<u8>, Vec<u8>)> for Tree {
+    fn extend<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            if let Err(err) = self.insert(&key, &value) {
+                panic!("[AVL]: Rejected entry in extend: {}", err);
+            }
+        }
+    }
+}
+
+/// Collects into a height-balanced tree, panicking like `Extend`.
+impl FromIterator<(Vec<u8>, Vec<u8>)> for Tree {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(iter: I) -> Self {
+        let mut tree = Tree::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+/// Iterator returned by `Tree::iter_with_proofs`.
+pub struct ProofIter<'a> {
+    compare: KeyComparator,
+    counters: &'a Counters,
+    end: Bound<&'a [u8]>,
+    /// Nodes still to be yielded, with their depth in the tree.
+    stack: Vec<(&'a Node, usize)>,
+    /// Path node contributed by the ancestor at each depth of the current
+    /// descent, root first.
+    path: Vec<ProofPathNode>,
 }
 
+impl<'a> ProofIter<'a> {
+    fn descend(&mut self, mut node_ref: &'a NodeRef, mut depth: usize, start: Bound<&[u8]>) {
+        while let Some(node) = node_ref {
+            let node_key: &[u8] = node.key.as_ref();
+            let after_start = match start {
+                Bound::Included(s) => (self.compare)(node_key, s) != Ordering::Less,
+                Bound::Excluded(s) => (self.compare)(node_key, s) == Ordering::Greater,
+                Bound::Unbounded => true,
+            };
+            self.path.truncate(depth);
+            if after_start {
+                self.stack.push((node, depth));
+                let mut suffix = node.hash.clone();
+                suffix.extend(node.right_hash().unwrap_or(&[]));
+                self.path.push(ProofPathNode {
+                    prefix: vec![],
+                    suffix,
+                });
+                node_ref = &node.left;
+            } else {
+                let mut prefix = node.left_hash().unwrap_or(&[]).to_vec();
+                prefix.extend(node.hash.iter());
+                self.path.push(ProofPathNode {
+                    prefix,
+                    suffix: vec![],
+                });
+                node_ref = &node.right;
+            }
+            depth += 1;
+        }
+    }
+}
+
+impl<'a> Iterator for ProofIter<'a> {
+    type Item = (&'a [u8], &'a [u8], Proof);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (node, depth) = self.stack.pop()?;
+        let node_key: &[u8] = node.key.as_ref();
+        let before_end = match self.end {
+            Bound::Included(e) => (self.compare)(node_key, e) != Ordering::Greater,
+            Bound::Excluded(e) => (self.compare)(node_key, e) == Ordering::Less,
+            Bound::Unbounded => true,
+        };
+        if !before_end {
+            self.stack.clear();
+            return None;
+        }
+
+        let mut path = Vec::with_capacity(depth + 1);
+        path.push(ProofPathNode {
+            prefix: node.left_hash().unwrap_or(&[]).to_vec(),
+            suffix: node.right_hash().unwrap_or(&[]).to_vec(),
+        });
+        path.extend(self.path[..depth].iter().rev().cloned());
+        let proof = Proof {
+            key: node.key.clone(),
+            value: node.value.clone(),
+            path,
+        };
+
+        if node.right.is_some() {
+            let mut prefix = node.left_hash().unwrap_or(&[]).to_vec();
+            prefix.extend(node.hash.iter());
+            self.path.truncate(depth);
+            self.path.push(ProofPathNode {
+                prefix,
+                suffix: vec![],
+            });
+            self.descend(&node.right, depth + 1, Bound::Unbounded);
+        }
+        self.counters.record_proof();
+        Some((node_key, node.value.as_ref(), proof))
+    }
+}
+
+const NODE_ABSENT: u8 = 0;
+const NODE_PRESENT: u8 = 1;
+const LAYOUT_WEIGHT_BALANCED: u8 = 2;
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::error::ErrorKind;
 
     #[test]
     fn test_simple_tree() {
@@ -230,7 +1582,7 @@ mod test {
         let now = std::time::Instant::now();
         for i in 0u32..10000u32 {
             let bytes = i.to_le_bytes();
-            tree.insert(&bytes, &bytes);
+            tree.insert(&bytes, &bytes).unwrap();
             assert!(tree.validate());
         }
         for i in 0u32..10000u32 {
@@ -246,8 +1598,9 @@ mod test {
         let nodes: [u32; 9] = [100, 50, 150, 25, 75, 125, 175, 65, 85];
         let mut hashs = vec![];
         for node in nodes {
-            tree.insert(&node.to_le_bytes(), &node.to_le_bytes());
-            hashs.push(hash_array(&[&node.to_le_bytes(), &node.to_le_bytes()]));
+            tree.insert(&node.to_le_bytes(), &node.to_le_bytes())
+                .unwrap();
+            hashs.push(leaf_hash(&node.to_le_bytes(), &node.to_le_bytes()));
         }
         assert_eq!(3, tree.root.as_ref().unwrap().height);
         assert_eq!(
@@ -273,12 +1626,875 @@ mod test {
         assert!(root.eq(tree.root_hash().unwrap()))
     }
 
+    #[test]
+    fn test_stats() {
+        let mut tree = Tree::new();
+        tree.insert(b"a", b"1").unwrap();
+        tree.insert(b"b", b"2").unwrap();
+        tree.get(b"a");
+        tree.get(b"c");
+        assert!(tree.contains_key(b"b"));
+        tree.get_by_index(0).unwrap();
+        assert_eq!(None, tree.get_by_index(2));
+        tree.get_proof(b"b").unwrap();
+        tree.remove(b"a");
+        let stats = tree.stats();
+        assert_eq!(5, stats.gets);
+        assert_eq!(3, stats.hits);
+        assert_eq!(2, stats.misses);
+        assert_eq!(2, stats.sets);
+        assert_eq!(1, stats.deletes);
+        assert_eq!(1, stats.proofs);
+        assert!(stats.nodes_hashed >= 3);
+        assert_eq!(stats, tree.clone().stats());
+
+        let copy = tree.clone();
+        tree.reset_stats();
+        assert_eq!(TreeStats::default(), tree.stats());
+        assert_eq!(copy, tree);
+    }
+
+    #[test]
+    fn test_empty_value() {
+        let mut tree = Tree::new();
+        tree.insert(b"a", b"1").unwrap();
+        tree.insert(b"b", b"").unwrap();
+        tree.insert(b"c", b"3").unwrap();
+        assert_eq!(Some(&b""[..]), tree.get(b"b"));
+        assert_eq!(None, tree.get(b"d"));
+
+        let proof = tree.get_proof(b"b").unwrap();
+        assert!(tree.verify_existence(b"b", b"", &proof).is_ok());
+
+        tree.insert(b"d", b"e").unwrap();
+        let mut moved = tree.get_proof(b"d").unwrap();
+        (moved.key, moved.value) = (b"de".to_vec(), vec![]);
+        assert!(tree.verify_existence(b"de", b"", &moved).is_err());
+        assert_eq!(Some(b"".to_vec()), tree.insert(b"b", b"2").unwrap());
+    }
+
+    #[test]
+    fn test_update_rehashes() {
+        let mut updated = Tree::new();
+        let mut fresh = Tree::new();
+        for i in 0u32..100u32 {
+            let bytes = i.to_le_bytes();
+            updated.insert(&bytes, b"old").unwrap();
+            fresh.insert(&bytes, &bytes).unwrap();
+        }
+        for i in 0u32..100u32 {
+            let bytes = i.to_le_bytes();
+            updated.insert(&bytes, &bytes).unwrap();
+        }
+        assert_eq!(fresh.root_hash(), updated.root_hash());
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut tree = Tree::new();
+        for i in 0u32..1000u32 {
+            let bytes = i.to_le_bytes();
+            tree.insert(&bytes, &bytes).unwrap();
+        }
+        for i in (0u32..1000u32).step_by(3) {
+            let bytes = i.to_le_bytes();
+            assert_eq!(Some(bytes.to_vec()), tree.remove(&bytes));
+            assert!(tree.validate());
+        }
+        assert_eq!(None, tree.remove(&0u32.to_le_bytes()));
+
+        for i in 0u32..1000u32 {
+            let bytes = i.to_le_bytes();
+            assert_eq!(i % 3 != 0, tree.get(&bytes).is_some());
+            if i % 3 != 0 {
+                let proof = tree.get_proof(&bytes).unwrap();
+                assert!(tree.verify_existence(&bytes, &bytes, &proof).is_ok());
+            }
+        }
+        for i in 0u32..1000u32 {
+            tree.remove(&i.to_le_bytes());
+        }
+        assert!(tree.root.is_none());
+    }
+
+    #[test]
+    fn test_remove_keeps_balance() {
+        // Removals leave balanced siblings behind, which must be fixed with a
+        // single rotation.
+        let mut tree = Tree::new();
+        let mut x = 1u64;
+        for _ in 0..5000 {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            let key = (x % 200).to_be_bytes();
+            if x.is_multiple_of(3) {
+                tree.remove(&key);
+            } else {
+                tree.insert(&key, &key).unwrap();
+            }
+            assert!(tree.root.is_none() || tree.validate());
+        }
+    }
+
+    #[test]
+    fn test_len() {
+        let mut tree = Tree::new();
+        assert!(tree.is_empty());
+        assert_eq!(0, tree.len());
+        for i in 0u32..100u32 {
+            tree.insert(&i.to_be_bytes(), &[]).unwrap();
+        }
+        tree.insert(&0u32.to_be_bytes(), b"updated").unwrap();
+        assert_eq!(100, tree.len());
+        for i in (0u32..100u32).step_by(2) {
+            tree.remove(&i.to_be_bytes());
+        }
+        assert_eq!(50, tree.len());
+        assert!(!tree.is_empty());
+
+        tree.clear();
+        assert!(tree.is_empty());
+        assert_eq!(None, tree.get(&1u32.to_be_bytes()));
+        tree.insert(b"reused", b"").unwrap();
+        assert_eq!(1, tree.len());
+    }
+
+    #[test]
+    fn test_remove_many() {
+        let mut tree = Tree::new();
+        let mut expected = Tree::new();
+        for i in 0u32..1000u32 {
+            let bytes = i.to_be_bytes();
+            tree.insert(&bytes, &bytes).unwrap();
+            if i % 7 != 0 && !(200..600).contains(&i) {
+                expected.insert(&bytes, &bytes).unwrap();
+            }
+        }
+        let doomed: Vec<[u8; 4]> = (0u32..1000u32)
+            .filter(|i| i % 7 == 0 || (200..600).contains(i))
+            .rev()
+            .map(u32::to_be_bytes)
+            .collect();
+        let mut keys: Vec<&[u8]> = doomed.iter().map(|k| &k[..]).collect();
+        keys.push(&doomed[0]);
+        keys.push(b"absent");
+
+        let removed = tree.remove_many(&keys);
+        assert_eq!(keys.len(), removed.len());
+        for (key, value) in doomed.iter().zip(&removed) {
+            assert_eq!(Some(key.to_vec()), *value);
+        }
+        assert_eq!([None, None], removed[doomed.len()..]);
+        assert!(tree.validate());
+        assert_eq!(
+            expected.root.as_ref().unwrap().size,
+            tree.root.as_ref().unwrap().size
+        );
+        let mut entries = vec![];
+        tree.for_each_with_prefix(b"", |k, v| entries.push((k.to_vec(), v.to_vec())));
+        let mut expected_entries = vec![];
+        expected.for_each_with_prefix(b"", |k, v| expected_entries.push((k.to_vec(), v.to_vec())));
+        assert_eq!(expected_entries, entries);
+
+        let key = 1u32.to_be_bytes();
+        let proof = tree.get_proof(&key).unwrap();
+        assert!(tree.verify_existence(&key, &key, &proof).is_ok());
+
+        let all: Vec<Vec<u8>> = entries.into_iter().map(|(k, _)| k).collect();
+        let all: Vec<&[u8]> = all.iter().map(|k| k.as_slice()).collect();
+        assert!(tree.remove_many(&all).iter().all(Option::is_some));
+        assert!(tree.root.is_none());
+    }
+
+    #[test]
+    fn test_retain() {
+        for policy in [BalancePolicy::Height, BalancePolicy::Weight] {
+            let mut tree = Tree::new().with_balance_policy(policy);
+            let mut expected = Tree::new().with_balance_policy(policy);
+            for i in 0u32..1000u32 {
+                let key = [&b"ns"[..], &(i % 3).to_be_bytes(), &i.to_be_bytes()].concat();
+                tree.insert(&key, &[]).unwrap();
+                if i % 3 != 1 {
+                    expected.insert(&key, &[]).unwrap();
+                }
+            }
+            let mut seen = vec![];
+            tree.retain(|key, _| {
+                seen.push(key.to_vec());
+                !key.starts_with(&[&b"ns"[..], &1u32.to_be_bytes()].concat())
+            });
+            assert_eq!(1000, seen.len());
+            assert!(seen.windows(2).all(|pair| pair[0] < pair[1]));
+            match policy {
+                BalancePolicy::Height => assert!(tree.validate()),
+                BalancePolicy::Weight => assert!(weight_balanced(&tree.root)),
+            }
+            assert!(tree.iter().eq(expected.iter()));
+
+            let root_hash = tree.root_hash().cloned();
+            let hashed = nodes_hashed();
+            tree.retain(|_, _| true);
+            assert_eq!(hashed, nodes_hashed());
+            assert_eq!(root_hash.as_ref(), tree.root_hash());
+        }
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        for policy in [BalancePolicy::Height, BalancePolicy::Weight] {
+            let mut tree = Tree::new().with_balance_policy(policy);
+            let mut expected = Tree::new().with_balance_policy(policy);
+            for i in 0u32..300u32 {
+                tree.insert(&(i * 2).to_be_bytes(), &i.to_le_bytes())
+                    .unwrap();
+                expected
+                    .insert(&(i * 2).to_be_bytes(), &i.to_le_bytes())
+                    .unwrap();
+            }
+            let mut ops = vec![];
+            for i in 0u32..500u32 {
+                let key = ((i * 7919) % 700).to_be_bytes().to_vec();
+                ops.push(match i % 3 {
+                    0 => Op::Delete(key),
+                    _ => Op::Set(key, i.to_be_bytes().to_vec()),
+                });
+            }
+            let previous = tree.apply_batch(&ops).unwrap();
+            let sequential: Vec<_> = ops.iter().map(|op| expected.apply(op).unwrap()).collect();
+            assert_eq!(sequential, previous);
+            match policy {
+                BalancePolicy::Height => assert!(tree.validate()),
+                BalancePolicy::Weight => assert!(weight_balanced(&tree.root)),
+            }
+            assert_eq!(expected.len(), tree.len());
+            assert!(tree.iter().eq(expected.iter()));
+        }
+    }
+
+    #[test]
+    fn test_apply_batch_is_atomic() {
+        let mut tree = Tree::new().with_max_size(3);
+        tree.insert(b"a", b"1").unwrap();
+        tree.insert(b"b", b"2").unwrap();
+        let before = tree.clone();
+
+        let ops = [Op::Delete(b"a".to_vec()), Op::Set(vec![], b"x".to_vec())];
+        assert!(tree.apply_batch(&ops).is_err());
+        assert_eq!(before, tree);
+
+        let ops = [
+            Op::Set(b"c".to_vec(), b"3".to_vec()),
+            Op::Set(b"d".to_vec(), b"4".to_vec()),
+        ];
+        let err = tree.apply_batch(&ops).unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+        assert_eq!(before, tree);
+
+        let ops = [
+            Op::Set(b"c".to_vec(), b"3".to_vec()),
+            Op::Delete(b"a".to_vec()),
+            Op::Set(b"d".to_vec(), b"4".to_vec()),
+        ];
+        let previous = tree.apply_batch(&ops).unwrap();
+        assert_eq!(vec![None, Some(b"1".to_vec()), None], previous);
+        assert_eq!(3, tree.len());
+
+        let mut tree = Tree::new().with_max_height(2);
+        for key in [b"a", b"b", b"c"] {
+            tree.insert(key, key).unwrap();
+        }
+        let before = tree.clone();
+        let ops: Vec<Op> = (b'd'..=b'z').map(|k| Op::Set(vec![k], vec![])).collect();
+        let err = tree.apply_batch(&ops).unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+        assert_eq!(before, tree);
+        tree.apply_batch(&ops[..1]).unwrap();
+        assert_eq!(2, tree.root.as_ref().unwrap().height);
+    }
+
+    #[test]
+    fn test_from_sorted_iter() {
+        let entries = (0u32..1000u32).map(|i| (i.to_be_bytes().to_vec(), i.to_le_bytes().to_vec()));
+        let tree = Tree::from_sorted_iter(entries.clone()).unwrap();
+        assert!(tree.validate());
+        assert_eq!(1000, tree.len());
+        assert_eq!(9, tree.root.as_ref().unwrap().height);
+        assert!(tree
+            .iter()
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+            .eq(entries));
+        assert!(Tree::from_sorted_iter(vec![]).unwrap().is_empty());
+
+        let unsorted = vec![(b"b".to_vec(), vec![]), (b"a".to_vec(), vec![])];
+        let err = Tree::from_sorted_iter(unsorted).unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+        let duplicate = vec![(b"a".to_vec(), vec![]), (b"a".to_vec(), vec![])];
+        assert!(Tree::from_sorted_iter(duplicate).is_err());
+    }
+
+    #[test]
+    fn test_extend_and_collect() {
+        let mut tree: Tree = (0u32..100u32)
+            .map(|i| (i.to_be_bytes().to_vec(), vec![]))
+            .collect();
+        assert_eq!(100, tree.len());
+        tree.extend((50u32..150u32).map(|i| (i.to_be_bytes().to_vec(), b"new".to_vec())));
+        assert!(tree.validate());
+        assert_eq!(150, tree.len());
+        assert_eq!(Some(&[][..]), tree.get(&49u32.to_be_bytes()));
+        assert_eq!(Some(&b"new"[..]), tree.get(&50u32.to_be_bytes()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Rejected entry")]
+    fn test_extend_rejects_invalid_entry() {
+        let _: Tree = vec![(vec![], vec![])].into_iter().collect();
+    }
+
+    #[test]
+    fn test_split_off() {
+        for policy in [BalancePolicy::Height, BalancePolicy::Weight] {
+            for at in [0u32, 1, 250, 499, 500, 1000] {
+                let mut tree = Tree::new().with_balance_policy(policy);
+                for i in 0u32..500u32 {
+                    tree.insert(&(i * 2).to_be_bytes(), &i.to_le_bytes())
+                        .unwrap();
+                }
+                let upper = tree.split_off(&at.to_be_bytes());
+                assert_eq!(policy, upper.balance);
+                assert_eq!(500, tree.len() + upper.len());
+                assert_eq!((at as usize).div_ceil(2).min(500), tree.len());
+                assert!(tree.iter().all(|(key, _)| key < &at.to_be_bytes()[..]));
+                assert!(upper.iter().all(|(key, _)| key >= &at.to_be_bytes()[..]));
+                for half in [&tree, &upper] {
+                    match policy {
+                        BalancePolicy::Height => assert!(half.is_empty() || half.validate()),
+                        BalancePolicy::Weight => assert!(weight_balanced(&half.root)),
+                    }
+                    if let Some((key, value)) = half.get_by_index(half.len() / 2) {
+                        let proof = half.get_proof(key).unwrap();
+                        assert!(half.verify_existence(key, value, &proof).is_ok());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_append() {
+        for policy in [BalancePolicy::Height, BalancePolicy::Weight] {
+            let build = |keys: &mut dyn Iterator<Item = u32>, value: &[u8]| {
+                let mut tree = Tree::new().with_balance_policy(policy);
+                for i in keys {
+                    tree.insert(&i.to_be_bytes(), value).unwrap();
+                }
+                tree
+            };
+            let mut tree = build(&mut (0u32..300u32), b"a");
+            tree.append(build(&mut (300u32..310u32), b"b")).unwrap();
+            tree.append(build(&mut (0u32..1000u32).step_by(7), b"c"))
+                .unwrap();
+            assert_eq!(
+                310 + (310u32..1000u32).filter(|i| i % 7 == 0).count(),
+                tree.len()
+            );
+            assert_eq!(Some(&b"c"[..]), tree.get(&7u32.to_be_bytes()));
+            assert_eq!(Some(&b"a"[..]), tree.get(&8u32.to_be_bytes()));
+            assert_eq!(Some(&b"b"[..]), tree.get(&302u32.to_be_bytes()));
+            assert!(tree.iter().map(|(key, _)| key).is_sorted());
+            match policy {
+                BalancePolicy::Height => assert!(tree.validate()),
+                BalancePolicy::Weight => assert!(weight_balanced(&tree.root)),
+            }
+            let mut expected = Tree::new().with_balance_policy(policy);
+            expected.extend(tree.iter().map(|(k, v)| (k.to_vec(), v.to_vec())));
+            assert!(tree.iter().eq(expected.iter()));
+        }
+
+        let mut tree = Tree::new();
+        let weighted = (0u32..100u32).map(|i| (i.to_be_bytes().to_vec(), vec![]));
+        let mut other = Tree::new().with_balance_policy(BalancePolicy::Weight);
+        other.extend(weighted);
+        tree.insert(&50u32.to_be_bytes(), b"").unwrap();
+        tree.append(other).unwrap();
+        assert_eq!(100, tree.len());
+        assert!(tree.validate());
+
+        let mut tree = Tree::new().with_max_size(3);
+        tree.insert(b"a", b"").unwrap();
+        let before = tree.clone();
+        let other: Tree = [b"b", b"c", b"d"]
+            .iter()
+            .map(|k| (k.to_vec(), vec![]))
+            .collect();
+        assert!(tree.append(other).is_err());
+        assert_eq!(before, tree);
+        let other: Tree = [b"a", b"c"].iter().map(|k| (k.to_vec(), vec![])).collect();
+        tree.append(other).unwrap();
+        assert_eq!(2, tree.len());
+
+        let mut tree = Tree::new().with_max_height(1);
+        tree.insert(b"b", b"").unwrap();
+        let before = tree.clone();
+        let other: Tree = [b"a", b"c", b"d"]
+            .iter()
+            .map(|k| (k.to_vec(), vec![]))
+            .collect();
+        assert!(tree.append(other).is_err());
+        assert_eq!(before, tree);
+        let other: Tree = [b"a"].iter().map(|k| (k.to_vec(), vec![])).collect();
+        tree.append(other).unwrap();
+        assert_eq!(1, tree.root.as_ref().unwrap().height);
+    }
+
+    #[test]
+    fn test_comparator() {
+        fn shortest_first(a: &[u8], b: &[u8]) -> Ordering {
+            a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+        }
+        let keys: [&[u8]; 7] = [b"ba", b"a", b"abc", b"b", b"ab", b"c", b"aa"];
+        let mut tree = Tree::new().with_comparator(shortest_first);
+        for key in keys {
+            tree.insert(key, key).unwrap();
+        }
+        assert!(tree.validate());
+        let ordered: Vec<&[u8]> = tree.iter().map(|(key, _)| key).collect();
+        let expected: [&[u8]; 7] = [b"a", b"b", b"c", b"aa", b"ab", b"ba", b"abc"];
+        assert_eq!(&expected[..], &ordered[..]);
+        let reversed: Vec<&[u8]> = tree.iter().rev().map(|(key, _)| key).collect();
+        assert!(reversed.iter().rev().eq(ordered.iter()));
+
+        assert_eq!(Some(&b"ab"[..]), tree.get(b"ab"));
+        let proof = tree.get_proof(b"ab").unwrap();
+        tree.verify_existence(b"ab", b"ab", &proof).unwrap();
+        let range: Vec<&[u8]> = tree.iter_range(b"c", b"ba").map(|(key, _)| key).collect();
+        assert_eq!(vec![&b"c"[..], b"aa", b"ab"], range);
+        let prefixed: Vec<&[u8]> = tree.iter_prefix(b"a").map(|(key, _)| key).collect();
+        assert_eq!(vec![&b"a"[..], b"aa", b"ab", b"abc"], prefixed);
+        assert_eq!(4, tree.iter_prefix(b"a").count());
+        assert_eq!((0, Some(tree.len())), tree.iter_prefix(b"a").size_hint());
+        assert_eq!(
+            Some(&b"abc"[..]),
+            tree.iter_prefix(b"a").next_back().map(|e| e.0)
+        );
+        assert_eq!(16, tree.usage_by_prefix(b"a"));
+
+        let mut cursor = tree.cursor();
+        cursor.seek(b"d");
+        assert_eq!(Some(&b"aa"[..]), cursor.key());
+        cursor.next();
+        assert_eq!(Some(&b"ab"[..]), cursor.key());
+        cursor.seek_for_prev(b"d");
+        assert_eq!(Some(&b"c"[..]), cursor.key());
+
+        let other: Tree = [&b"zz"[..], b"d"]
+            .iter()
+            .map(|k| (k.to_vec(), vec![]))
+            .collect();
+        tree.append(other).unwrap();
+        assert_eq!(Some(&b"d"[..]), tree.iter().nth(3).map(|e| e.0));
+        assert_eq!(Some(b"ab".to_vec()), tree.remove(b"ab"));
+        assert_eq!(8, tree.len());
+        assert!(tree.validate());
+
+        let mut bytes = vec![];
+        tree.serialize(&mut bytes).unwrap();
+        let restored = Tree::deserialize_ordered_by(&mut bytes.as_slice(), shortest_first).unwrap();
+        assert!(restored.iter().eq(tree.iter()));
+        assert!(Tree::deserialize(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_map_values() {
+        let mut tree = Tree::new();
+        let mut expected = Tree::new();
+        for i in 0u32..100u32 {
+            let key = i.to_be_bytes();
+            tree.insert(&key, &i.to_le_bytes()).unwrap();
+            expected.insert(&key, &(i * 2).to_le_bytes()).unwrap();
+        }
+        let mapped = tree
+            .map_values(|_, v| {
+                (u32::from_le_bytes(v.try_into().unwrap()) * 2)
+                    .to_le_bytes()
+                    .to_vec()
+            })
+            .unwrap();
+        assert_eq!(expected.root_hash(), mapped.root_hash());
+        assert_eq!(Some(&5u32.to_le_bytes()[..]), tree.get(&5u32.to_be_bytes()));
+        assert!(mapped.validate());
+
+        let err = tree
+            .map_values(|_, _| vec![0; MAX_VALUE_SIZE + 1])
+            .unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+    }
+
+    fn weight_balanced(node_ref: &NodeRef) -> bool {
+        match node_ref {
+            None => true,
+            Some(node) => {
+                !BalancePolicy::Weight.too_heavy(&node.left, &node.right)
+                    && !BalancePolicy::Weight.too_heavy(&node.right, &node.left)
+                    && node.size == 1 + node.left_size() + node.right_size()
+                    && weight_balanced(&node.left)
+                    && weight_balanced(&node.right)
+            }
+        }
+    }
+
+    #[test]
+    fn test_weight_balance_policy() {
+        let mut tree = Tree::new().with_balance_policy(BalancePolicy::Weight);
+        for i in 0u32..1000u32 {
+            let bytes = i.to_be_bytes();
+            tree.insert(&bytes, &bytes).unwrap();
+            assert!(weight_balanced(&tree.root));
+        }
+        for i in (0u32..1000u32).step_by(3) {
+            assert!(tree.remove(&i.to_be_bytes()).is_some());
+            assert!(weight_balanced(&tree.root));
+        }
+        let doomed: Vec<[u8; 4]> = (0u32..500u32).map(u32::to_be_bytes).collect();
+        let keys: Vec<&[u8]> = doomed.iter().map(|k| &k[..]).collect();
+        tree.remove_many(&keys);
+        assert!(weight_balanced(&tree.root));
+
+        let key = 998u32.to_be_bytes();
+        let proof = tree.get_proof(&key).unwrap();
+        assert!(tree.verify_existence(&key, &key, &proof).is_ok());
+
+        let mut bytes = vec![];
+        tree.serialize(&mut bytes).unwrap();
+        let restored = Tree::deserialize(&mut bytes.as_slice()).unwrap();
+        assert_eq!(tree, restored);
+        let mut height_balanced = vec![];
+        Tree::new().serialize(&mut height_balanced).unwrap();
+        assert_eq!(vec![NODE_ABSENT], height_balanced);
+    }
+
+    #[test]
+    fn test_drop_deep_tree() {
+        std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(|| {
+                let mut tree = Tree::new();
+                for i in 0u32..100_000u32 {
+                    let mut node = as_node_ref(i.to_be_bytes().to_vec(), vec![]).unwrap();
+                    node.right = tree.root.take();
+                    tree.root = Some(node);
+                }
+                drop(tree);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_insert_deep_tree() {
+        std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(|| {
+                let mut tree = Tree::new();
+                for i in (0u32..20_000u32).rev() {
+                    let mut node = as_node_ref(i.to_be_bytes().to_vec(), vec![]).unwrap();
+                    node.right = tree.root.take();
+                    node.update();
+                    tree.root = Some(node);
+                }
+                let key = 20_000u32.to_be_bytes();
+                assert_eq!(None, tree.insert(&key, b"deepest").unwrap());
+                assert_eq!(Some(&b"deepest"[..]), tree.get(&key));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_for_each_with_prefix() {
+        let mut tree = Tree::new();
+        for key in [&b"a"[..], b"ab", b"abc", b"abd", b"b", b"ba", b"aa"] {
+            tree.insert(key, key).unwrap();
+        }
+        let mut keys = vec![];
+        tree.for_each_with_prefix(b"ab", |k, _| keys.push(k.to_vec()));
+        assert_eq!(vec![b"ab".to_vec(), b"abc".to_vec(), b"abd".to_vec()], keys);
+    }
+
+    #[test]
+    fn test_usage_by_prefix() {
+        let mut tree = Tree::new();
+        tree.insert(b"bank/alice", b"100").unwrap();
+        tree.insert(b"bank/bob", b"5").unwrap();
+        tree.insert(b"staking/alice", b"").unwrap();
+        assert_eq!(22, tree.usage_by_prefix(b"bank/"));
+        assert_eq!(13, tree.usage_by_prefix(b"staking/"));
+        assert_eq!(35, tree.usage_by_prefix(b""));
+        assert_eq!(0, tree.usage_by_prefix(b"gov/"));
+    }
+
+    #[test]
+    fn test_paginate() {
+        let mut tree = Tree::new();
+        for i in 0u32..10u32 {
+            tree.insert(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        for reverse in [false, true] {
+            let mut cursor = None;
+            let mut keys = vec![];
+            loop {
+                let page = tree.paginate(cursor.as_deref(), 3, reverse).unwrap();
+                assert!(page.entries.len() <= 3);
+                keys.extend(page.entries.into_iter().map(|(key, _)| key));
+                cursor = page.next_cursor;
+                if cursor.is_none() {
+                    break;
+                }
+            }
+            let mut expected: Vec<Vec<u8>> =
+                (0u32..10u32).map(|i| i.to_be_bytes().to_vec()).collect();
+            if reverse {
+                expected.reverse();
+            }
+            assert_eq!(expected, keys);
+        }
+
+        let page = tree.paginate(None, 10, false).unwrap();
+        assert_eq!(10, page.entries.len());
+        assert_eq!(None, page.next_cursor);
+        assert!(Tree::new()
+            .paginate(None, 3, false)
+            .unwrap()
+            .entries
+            .is_empty());
+        let err = tree.paginate(None, 0, false).unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+    }
+
+    #[test]
+    fn test_contains_key() {
+        let mut tree = Tree::new();
+        tree.insert(b"key", b"").unwrap();
+        assert!(tree.contains_key(b"key"));
+        assert!(!tree.contains_key(b"other"));
+        tree.remove(b"key");
+        assert!(!tree.contains_key(b"key"));
+    }
+
+    #[test]
+    fn test_first_last_key_value() {
+        let mut tree = Tree::new();
+        assert_eq!(None, tree.first_key_value());
+        assert_eq!(None, tree.last_key_value());
+        for key in [&b"m"[..], b"c", b"x", b"a", b"z"] {
+            tree.insert(key, key).unwrap();
+        }
+        assert_eq!(Some((&b"a"[..], &b"a"[..])), tree.first_key_value());
+        assert_eq!(Some((&b"z"[..], &b"z"[..])), tree.last_key_value());
+    }
+
+    #[test]
+    fn test_get_with_index() {
+        let mut tree = Tree::new();
+        for i in (0u32..200u32).rev() {
+            tree.insert(&(i * 2).to_be_bytes(), &i.to_be_bytes())
+                .unwrap();
+            assert!(tree.validate());
+        }
+        for i in 0u32..200u32 {
+            let (index, value) = tree.get_with_index(&(i * 2).to_be_bytes()).unwrap();
+            assert_eq!(i as usize, index);
+            assert_eq!(&i.to_be_bytes()[..], value);
+            assert_eq!(None, tree.get_with_index(&(i * 2 + 1).to_be_bytes()));
+        }
+        tree.remove(&0u32.to_be_bytes());
+        assert_eq!(0, tree.get_with_index(&2u32.to_be_bytes()).unwrap().0);
+    }
+
+    #[test]
+    fn test_get_by_index() {
+        let mut tree = Tree::new();
+        assert_eq!(None, tree.get_by_index(0));
+        for i in (0u32..300u32).rev() {
+            tree.insert(&i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+        }
+        for i in 0u32..300u32 {
+            let (key, value) = tree.get_by_index(i as usize).unwrap();
+            assert_eq!(&i.to_be_bytes()[..], key);
+            assert_eq!(&i.to_le_bytes()[..], value);
+            assert_eq!(Some((i as usize, value)), tree.get_with_index(key));
+        }
+        assert_eq!(None, tree.get_by_index(300));
+    }
+
+    #[test]
+    fn test_iter_with_proofs() {
+        let mut tree = Tree::new();
+        for i in 0u32..500u32 {
+            tree.insert(&i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+        }
+        let root = tree.root_hash().unwrap().clone();
+        let mut count = 0u32;
+        for (key, value, proof) in tree.iter_with_proofs(Bound::Unbounded, Bound::Unbounded) {
+            assert_eq!(&count.to_be_bytes()[..], key);
+            assert_eq!(tree.get_proof(key).unwrap(), proof);
+            assert!(tree.verify_existence(key, value, &proof).is_ok());
+            assert!(proof.verify_root(&root).is_ok());
+            count += 1;
+        }
+        assert_eq!(500, count);
+
+        tree.reset_stats();
+        let start = 100u32.to_be_bytes();
+        let end = 200u32.to_be_bytes();
+        let keys: Vec<&[u8]> = tree
+            .iter_with_proofs(Bound::Excluded(&start), Bound::Included(&end))
+            .map(|(key, _, _)| key)
+            .collect();
+        assert_eq!(100, keys.len());
+        assert_eq!(&101u32.to_be_bytes()[..], keys[0]);
+        assert_eq!(&200u32.to_be_bytes()[..], keys[99]);
+        assert_eq!(100, tree.stats().proofs);
+    }
+
+    #[test]
+    fn test_growth_guards() {
+        let mut tree = Tree::new().with_max_size(3);
+        for key in [b"a", b"b", b"c"] {
+            tree.insert(key, key).unwrap();
+        }
+        assert!(tree.insert(b"d", b"d").is_err());
+        assert!(tree.insert(b"a", b"updated").is_ok());
+        assert_eq!(None, tree.get(b"d"));
+
+        let mut tree = Tree::new().with_max_height(3);
+        let mut rejected = false;
+        for i in 0u32..100u32 {
+            let hash = tree.root_hash().cloned();
+            match tree.insert(&i.to_be_bytes(), &i.to_be_bytes()) {
+                Ok(_) => {}
+                Err(e) => {
+                    assert_eq!(ErrorKind::InvalidInput, e.kind());
+                    assert_eq!(hash.as_ref(), tree.root_hash());
+                    rejected = true;
+                }
+            }
+            assert!(tree.root.as_ref().unwrap().height <= 3);
+        }
+        assert!(rejected);
+
+        // The new leaf lands at depth 3, but the rotation it triggers keeps
+        // the tree at height 2, as the same write made as a batch does.
+        let mut tree = Tree::new().with_max_height(2);
+        for key in [b"c", b"b", b"a"] {
+            tree.insert(key, key).unwrap();
+        }
+        let mut batched = tree.clone();
+        tree.insert(b"0", b"0").unwrap();
+        assert_eq!(2, tree.root.as_ref().unwrap().height);
+        batched
+            .apply_batch(&[Op::Set(b"0".to_vec(), b"0".to_vec())])
+            .unwrap();
+        assert_eq!(2, batched.root.as_ref().unwrap().height);
+    }
+
+    #[test]
+    fn test_insert_validation() {
+        let mut tree = Tree::new().with_reserved_prefixes(vec![b"_meta/".to_vec()]);
+        assert!(tree.insert(b"", b"value").is_err());
+        assert!(tree.insert(&vec![1u8; MAX_KEY_SIZE + 1], b"value").is_err());
+        assert!(tree.insert(b"key", &vec![1u8; MAX_VALUE_SIZE + 1]).is_err());
+        assert!(tree.insert(b"_meta/version", b"value").is_err());
+        assert!(tree.root.is_none());
+        assert!(tree.insert(b"_meta", b"value").is_ok());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut tree = Tree::new();
+        for i in 0u32..1000u32 {
+            let bytes = i.to_le_bytes();
+            tree.insert(&bytes, &bytes).unwrap();
+        }
+        let mut buf = vec![];
+        tree.serialize(&mut buf).unwrap();
+        let restored = Tree::deserialize(&mut buf.as_slice()).unwrap();
+        assert_eq!(tree, restored);
+
+        let mut buf = vec![];
+        Tree::new().serialize(&mut buf).unwrap();
+        assert_eq!(Tree::new(), Tree::deserialize(&mut buf.as_slice()).unwrap());
+        assert!(Tree::deserialize(&mut [2u8].as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_deep_input() {
+        let depth = 100_000u32;
+        let mut bytes = vec![];
+        for i in 0..depth {
+            bytes.push(NODE_PRESENT);
+            bytes.extend_from_slice(&(depth - i - 1).to_be_bytes());
+            for field in [&i.to_be_bytes()[..], b"", b"", b""] {
+                write_bytes(&mut bytes, field).unwrap();
+            }
+        }
+        bytes.resize(bytes.len() + depth as usize + 1, NODE_ABSENT);
+        let tree = Tree::deserialize_unverified(&mut bytes.as_slice()).unwrap();
+        assert_eq!(depth as usize, tree.len());
+        assert!(Tree::deserialize(&mut bytes.as_slice()).is_err());
+        let err = Tree::deserialize(&mut &bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(ErrorKind::Corruption, err.kind());
+    }
+
+    #[test]
+    fn test_deserialize_checks() {
+        let mut tree = Tree::new();
+        for i in 0u32..50u32 {
+            tree.insert(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        let mut bytes = vec![];
+        tree.serialize(&mut bytes).unwrap();
+        assert_eq!(tree, Tree::deserialize(&mut bytes.as_slice()).unwrap());
+
+        // Flip the last byte of the last node's value.
+        let value_end = bytes.len() - 2 - 2 * (4 + 32);
+        bytes[value_end - 1] ^= 1;
+        assert!(Tree::deserialize_unverified(&mut bytes.as_slice()).is_ok());
+        let err = Tree::deserialize(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(ErrorKind::Corruption, err.kind());
+
+        // Consistent hashes do not make up for misplaced keys or a lopsided
+        // shape.
+        let rehashed = |keys: &[&[u8]]| {
+            let mut root: NodeRef = None;
+            for key in keys.iter().rev() {
+                let mut node = as_node_ref(key.to_vec(), vec![]);
+                node.as_mut().unwrap().left = root;
+                node.as_mut().unwrap().update();
+                root = node;
+            }
+            let mut tree = Tree::new();
+            tree.root = root;
+            let mut bytes = vec![];
+            tree.serialize(&mut bytes).unwrap();
+            bytes
+        };
+        for bytes in [rehashed(&[b"b", b"c"]), rehashed(&[b"d", b"c", b"b", b"a"])] {
+            assert!(Tree::deserialize_unverified(&mut bytes.as_slice()).is_ok());
+            let err = Tree::deserialize(&mut bytes.as_slice()).unwrap_err();
+            assert_eq!(ErrorKind::Corruption, err.kind());
+        }
+    }
+
     #[test]
     fn test_proof() {
         let mut tree = Tree::new();
         for i in 0u32..10000u32 {
             let bytes = i.to_le_bytes();
-            tree.insert(&bytes, &bytes);
+            tree.insert(&bytes, &bytes).unwrap();
         }
 
         for i in 0u32..10000u32 {
@@ -286,5 +2502,9 @@ mod test {
             let proof = tree.get_proof(&bytes).unwrap();
             assert!(tree.verify_existence(&bytes, &bytes, &proof).is_ok());
         }
+
+        let proof = tree.get_proof(&1u32.to_le_bytes()).unwrap();
+        let bytes = 2u32.to_le_bytes();
+        assert!(tree.verify_existence(&bytes, &bytes, &proof).is_err());
     }
 }
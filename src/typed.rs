@@ -0,0 +1,164 @@
+use std::marker::PhantomData;
+
+use crate::error::{Error, Result};
+use crate::hash::Hash;
+use crate::keys::{decode_i64, decode_u64, encode_i64, encode_u64};
+use crate::tree::Tree;
+
+/// Conversion between a typed key or value and the bytes stored in a tree.
+/// Key encodings should sort byte-wise in the type's own order, so that
+/// iteration follows it.
+pub trait Codec: Sized {
+    fn encode(&self) -> Vec<u8>;
+    fn decode(bytes: &[u8]) -> Result<Self>;
+}
+
+impl Codec for Vec<u8> {
+    fn encode(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl Codec for String {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        String::from_utf8(bytes.to_vec()).map_err(|e| Error::Decode(e.to_string()))
+    }
+}
+
+impl Codec for u64 {
+    fn encode(&self) -> Vec<u8> {
+        encode_u64(*self).to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        decode_u64(bytes)
+    }
+}
+
+impl Codec for i64 {
+    fn encode(&self) -> Vec<u8> {
+        encode_i64(*self).to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        decode_i64(bytes)
+    }
+}
+
+/// A `Tree` storing typed keys and values, encoded with `Codec` at every
+/// call. Hashes, proofs and persistence stay those of the underlying byte
+/// tree, which `tree` exposes.
+pub struct TypedTree<K: Codec, V: Codec> {
+    tree: Tree,
+    _types: PhantomData<(K, V)>,
+}
+
+impl<K: Codec, V: Codec> TypedTree<K, V> {
+    pub fn new(tree: Tree) -> Self {
+        TypedTree {
+            tree,
+            _types: PhantomData,
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        self.tree.get(&key.encode()).map(V::decode).transpose()
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.tree.contains_key(&key.encode())
+    }
+
+    /// Insert `value`, returning the old one. Fails, leaving the tree
+    /// unchanged, if the old value does not decode.
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<Option<V>> {
+        let key = key.encode();
+        let old = self.decode_stored(&key)?;
+        self.tree.insert(&key, &value.encode())?;
+        Ok(old)
+    }
+
+    /// Remove `key`, returning its value. Fails, leaving the tree unchanged,
+    /// if the value does not decode.
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>> {
+        let key = key.encode();
+        let old = self.decode_stored(&key)?;
+        self.tree.remove(&key);
+        Ok(old)
+    }
+
+    fn decode_stored(&self, key: &[u8]) -> Result<Option<V>> {
+        self.tree.get_untracked(key).map(V::decode).transpose()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn root_hash(&self) -> Option<&Hash> {
+        self.tree.root_hash()
+    }
+
+    /// Decoded entries in the byte order of their encoded keys.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Result<(K, V)>> + '_ {
+        self.tree
+            .iter()
+            .map(|(key, value)| Ok((K::decode(key)?, V::decode(value)?)))
+    }
+
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> Tree {
+        self.tree
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_typed_tree() {
+        let mut tree: TypedTree<i64, String> = TypedTree::new(Tree::new());
+        for v in [3i64, -7, 0, 42, -1] {
+            assert_eq!(None, tree.insert(&v, &v.to_string()).unwrap());
+        }
+        assert_eq!(
+            Some("3".to_string()),
+            tree.insert(&3, &"three".to_string()).unwrap()
+        );
+        assert_eq!(Some("three".to_string()), tree.get(&3).unwrap());
+        assert_eq!(None, tree.get(&4).unwrap());
+        assert_eq!(Some("-7".to_string()), tree.remove(&-7).unwrap());
+        assert!(!tree.contains_key(&-7));
+
+        let keys: Vec<i64> = tree.iter().map(|entry| entry.unwrap().0).collect();
+        assert_eq!(vec![-1, 0, 3, 42], keys);
+        assert_eq!(4, tree.len());
+
+        let mut bytes = tree.into_tree();
+        bytes.insert(&encode_i64(5), b"\xff").unwrap();
+        let mut tree: TypedTree<i64, String> = TypedTree::new(bytes);
+        assert_eq!(ErrorKind::Corruption, tree.get(&5).unwrap_err().kind());
+        let root_hash = tree.root_hash().cloned();
+        assert!(tree.insert(&5, &"five".to_string()).is_err());
+        assert!(tree.remove(&5).is_err());
+        assert_eq!(root_hash.as_ref(), tree.root_hash());
+        assert_eq!(Some(&b"\xff"[..]), tree.tree().get(&encode_i64(5)));
+    }
+}
@@ -0,0 +1,209 @@
+use std::ops::ControlFlow;
+
+use crate::node::{Node, NodeRef};
+use crate::tree::Tree;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum TraversalOrder {
+    PreOrder,
+    InOrder,
+    PostOrder,
+}
+
+/// What `Tree::walk` should do after visiting a node.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Visit {
+    /// Descend into the node's children.
+    Continue,
+    /// Leave the node's subtree unvisited but carry on elsewhere.
+    Skip,
+    /// End the walk.
+    Stop,
+}
+
+/// Read-only view of a node handed to `Tree::visit_nodes`.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct NodeInfo<'a> {
+    pub key: &'a [u8],
+    pub value: &'a [u8],
+    pub hash: &'a [u8],
+    pub merkle_hash: &'a [u8],
+    pub height: u32,
+    pub size: usize,
+    /// Distance from the root, which has depth 0.
+    pub depth: usize,
+}
+
+impl<'a> NodeInfo<'a> {
+    fn new(node: &'a Node, depth: usize) -> Self {
+        NodeInfo {
+            key: node.key.as_ref(),
+            value: node.value.as_ref(),
+            hash: node.hash.as_ref(),
+            merkle_hash: node.merkle_hash.as_ref(),
+            height: node.height,
+            size: node.size,
+            depth,
+        }
+    }
+}
+
+impl Tree {
+    /// Call `f` for every node in the given order.
+    pub fn visit_nodes<F: FnMut(NodeInfo)>(&self, order: TraversalOrder, mut f: F) {
+        visit_recursive(&self.root, order, 0, &mut f)
+    }
+
+    /// Visit nodes in pre-order, letting `f` prune the subtree below each
+    /// node (e.g. one whose merkle hash is already known) or stop the walk.
+    /// Returns `false` if the walk was stopped.
+    pub fn walk<F: FnMut(NodeInfo) -> Visit>(&self, mut f: F) -> bool {
+        walk_recursive(&self.root, 0, &mut f)
+    }
+
+    /// Call `f` on each entry in ascending key order until it breaks,
+    /// returning the break value.
+    pub fn traverse<B, F: FnMut(&[u8], &[u8]) -> ControlFlow<B>>(
+        &self,
+        mut f: F,
+    ) -> ControlFlow<B> {
+        traverse_recursive(&self.root, &mut f)
+    }
+}
+
+fn traverse_recursive<B, F: FnMut(&[u8], &[u8]) -> ControlFlow<B>>(
+    node_ref: &NodeRef,
+    f: &mut F,
+) -> ControlFlow<B> {
+    if let Some(node) = node_ref {
+        traverse_recursive(&node.left, f)?;
+        f(&node.key, &node.value)?;
+        traverse_recursive(&node.right, f)?;
+    }
+    ControlFlow::Continue(())
+}
+
+fn walk_recursive<F: FnMut(NodeInfo) -> Visit>(
+    node_ref: &NodeRef,
+    depth: usize,
+    f: &mut F,
+) -> bool {
+    let node = match node_ref {
+        Some(node) => node,
+        None => return true,
+    };
+    match f(NodeInfo::new(node, depth)) {
+        Visit::Continue => {
+            walk_recursive(&node.left, depth + 1, f) && walk_recursive(&node.right, depth + 1, f)
+        }
+        Visit::Skip => true,
+        Visit::Stop => false,
+    }
+}
+
+fn visit_recursive<F: FnMut(NodeInfo)>(
+    node_ref: &NodeRef,
+    order: TraversalOrder,
+    depth: usize,
+    f: &mut F,
+) {
+    if let Some(node) = node_ref {
+        if order == TraversalOrder::PreOrder {
+            f(NodeInfo::new(node, depth));
+        }
+        visit_recursive(&node.left, order, depth + 1, f);
+        if order == TraversalOrder::InOrder {
+            f(NodeInfo::new(node, depth));
+        }
+        visit_recursive(&node.right, order, depth + 1, f);
+        if order == TraversalOrder::PostOrder {
+            f(NodeInfo::new(node, depth));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys(tree: &Tree, order: TraversalOrder) -> Vec<u8> {
+        let mut keys = vec![];
+        tree.visit_nodes(order, |info| keys.push(info.key[0]));
+        keys
+    }
+
+    #[test]
+    fn test_visit_nodes() {
+        let mut tree = Tree::new();
+        for key in [2u8, 1, 3] {
+            tree.insert(&[key], &[key]).unwrap();
+        }
+        assert_eq!(vec![2, 1, 3], keys(&tree, TraversalOrder::PreOrder));
+        assert_eq!(vec![1, 2, 3], keys(&tree, TraversalOrder::InOrder));
+        assert_eq!(vec![1, 3, 2], keys(&tree, TraversalOrder::PostOrder));
+
+        let mut root = None;
+        tree.visit_nodes(TraversalOrder::PreOrder, |info| {
+            if info.depth == 0 {
+                root = Some((info.height, info.size, info.merkle_hash.to_vec()));
+            }
+        });
+        assert_eq!(Some((1, 3, tree.root_hash().unwrap().clone())), root);
+    }
+
+    #[test]
+    fn test_walk() {
+        let mut tree = Tree::new();
+        for key in [4u8, 2, 6, 1, 3, 5, 7] {
+            tree.insert(&[key], &[key]).unwrap();
+        }
+        let mut visited = vec![];
+        assert!(tree.walk(|info| {
+            visited.push(info.key[0]);
+            if info.key == [2] {
+                Visit::Skip
+            } else {
+                Visit::Continue
+            }
+        }));
+        assert_eq!(vec![4, 2, 6, 5, 7], visited);
+
+        let mut visited = vec![];
+        assert!(!tree.walk(|info| {
+            visited.push(info.key[0]);
+            if info.key == [3] {
+                Visit::Stop
+            } else {
+                Visit::Continue
+            }
+        }));
+        assert_eq!(vec![4, 2, 1, 3], visited);
+    }
+
+    #[test]
+    fn test_traverse() {
+        let mut tree = Tree::new();
+        for key in [4u8, 2, 6, 1, 3, 5, 7] {
+            tree.insert(&[key], &[key * 10]).unwrap();
+        }
+        let mut visited = vec![];
+        let found = tree.traverse(|key, value| {
+            visited.push(key[0]);
+            if value[0] > 25 {
+                ControlFlow::Break(key[0])
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(ControlFlow::Break(3), found);
+        assert_eq!(vec![1, 2, 3], visited);
+
+        let mut count = 0;
+        let done = tree.traverse(|_, _| -> ControlFlow<()> {
+            count += 1;
+            ControlFlow::Continue(())
+        });
+        assert_eq!(ControlFlow::Continue(()), done);
+        assert_eq!(7, count);
+    }
+}